@@ -0,0 +1,41 @@
+use crate::webreq::{
+    ResolveError, cache::MooseCache, client::MooseClient, get_irclines, get_search,
+    resolve_moosename,
+};
+
+use super::{MooseBackend, SearchPage};
+
+/// The moose2 web API.
+pub struct HttpBackend {
+    client: MooseClient,
+    url: String,
+    cache: MooseCache,
+}
+
+impl HttpBackend {
+    pub fn new(url: String, cache: MooseCache) -> Self {
+        Self {
+            client: MooseClient::new(),
+            url,
+            cache,
+        }
+    }
+}
+
+impl MooseBackend for HttpBackend {
+    async fn resolve(&self, moose: &str) -> Result<String, ResolveError> {
+        resolve_moosename(&self.client, &self.url, &self.cache, moose).await
+    }
+
+    async fn irclines(&self, moose: &str) -> Result<String, ResolveError> {
+        get_irclines(&self.client, &self.url, &self.cache, moose).await
+    }
+
+    async fn search(&self, query: &str, page: usize) -> Result<SearchPage, ResolveError> {
+        get_search(&self.client, &self.url, &self.cache, query, page).await
+    }
+
+    fn image_url(&self, moose: &str) -> String {
+        format!("{}/img/{moose}", self.url)
+    }
+}
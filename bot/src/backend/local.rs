@@ -0,0 +1,138 @@
+use std::{io, path::PathBuf, time::SystemTime};
+
+use percent_encoding::percent_decode_str;
+
+use crate::webreq::{ResolveError, urlencode};
+
+use super::{MooseBackend, SearchHit, SearchPage};
+
+/// Number of moose shown per gallery page; used to give search results a page number.
+const GALLERY_PAGE_SIZE: usize = 12;
+/// Number of search results per page of results.
+const SEARCH_PAGE_SIZE: usize = 12;
+const IRC_EXT: &str = "irc";
+
+/// Serves moose from a directory, e.g. an air-gapped mirror of moose2.
+///
+/// Every moose is a `<url encoded name>.irc` file containing its IRC formatted art.
+pub struct LocalBackend {
+    dir: PathBuf,
+    url: String,
+}
+
+impl LocalBackend {
+    /// `url` is only used to create image links.
+    pub fn new(dir: PathBuf, url: String) -> Self {
+        Self { dir, url }
+    }
+
+    /// All moose as (url encoded name, modified time).
+    async fn moose(&self) -> Result<Vec<(String, SystemTime)>, ResolveError> {
+        let mut moose = vec![];
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != IRC_EXT) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let modified = entry.metadata().await?.modified()?;
+            moose.push((name.to_owned(), modified));
+        }
+        Ok(moose)
+    }
+}
+
+/// Resolved names are url encoded, so anything else can't be a file we made.
+fn is_encoded(moose: &str) -> bool {
+    !moose.is_empty()
+        && moose
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'%')
+}
+
+impl MooseBackend for LocalBackend {
+    async fn resolve(&self, moose: &str) -> Result<String, ResolveError> {
+        let not_found = || ResolveError::NotFound(moose.to_owned());
+        match moose {
+            "random" => {
+                let all = self.moose().await?;
+                if all.is_empty() {
+                    return Err(not_found());
+                }
+                Ok(all[rand::random_range(0..all.len())].0.clone())
+            }
+            "latest" => self
+                .moose()
+                .await?
+                .into_iter()
+                .max_by_key(|(_, modified)| *modified)
+                .map(|(name, _)| name)
+                .ok_or_else(not_found),
+            "oldest" => self
+                .moose()
+                .await?
+                .into_iter()
+                .min_by_key(|(_, modified)| *modified)
+                .map(|(name, _)| name)
+                .ok_or_else(not_found),
+            _ => {
+                let name = urlencode(moose.as_bytes()).to_string();
+                let path = self.dir.join(format!("{name}.{IRC_EXT}"));
+                if tokio::fs::try_exists(path).await? {
+                    Ok(name)
+                } else {
+                    Err(not_found())
+                }
+            }
+        }
+    }
+
+    async fn irclines(&self, moose: &str) -> Result<String, ResolveError> {
+        let not_found =
+            || ResolveError::NotFound(percent_decode_str(moose).decode_utf8_lossy().into_owned());
+        if !is_encoded(moose) {
+            return Err(not_found());
+        }
+        match tokio::fs::read_to_string(self.dir.join(format!("{moose}.{IRC_EXT}"))).await {
+            Ok(lines) => Ok(lines),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(not_found()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn search(&self, query: &str, page: usize) -> Result<SearchPage, ResolveError> {
+        let query = query.to_lowercase();
+        let mut names = self
+            .moose()
+            .await?
+            .into_iter()
+            .map(|(name, _)| percent_decode_str(&name).decode_utf8_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        let hits = names
+            .into_iter()
+            .enumerate()
+            .filter(|(_, name)| name.to_lowercase().contains(&query))
+            .map(|(i, name)| SearchHit {
+                name,
+                page: i / GALLERY_PAGE_SIZE,
+            })
+            .collect::<Vec<_>>();
+        Ok(SearchPage {
+            pages: hits.len().div_ceil(SEARCH_PAGE_SIZE),
+            total: Some(hits.len()),
+            hits: hits
+                .into_iter()
+                .skip(page.saturating_mul(SEARCH_PAGE_SIZE))
+                .take(SEARCH_PAGE_SIZE)
+                .collect(),
+        })
+    }
+
+    fn image_url(&self, moose: &str) -> String {
+        format!("{}/img/{moose}", self.url)
+    }
+}
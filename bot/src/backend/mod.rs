@@ -0,0 +1,80 @@
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+
+pub use self::http::HttpBackend;
+pub use self::local::LocalBackend;
+use crate::webreq::ResolveError;
+
+pub mod http;
+pub mod local;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub name: String,
+    /// gallery page the moose can be found on.
+    pub page: usize,
+}
+
+/// One page of search results.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    /// total number of result pages for the query.
+    pub pages: usize,
+    /// total number of results for the query, when the backend knows it.
+    #[serde(default)]
+    pub total: Option<usize>,
+}
+
+/// A source of moose.
+///
+/// Moose names given to [`MooseBackend::irclines`] and [`MooseBackend::image_url`]
+/// must come from [`MooseBackend::resolve`].
+pub trait MooseBackend {
+    /// Resolve a user provided moose name, or one of `random`, `latest`, `oldest`,
+    /// into a url encoded name of an existing moose.
+    fn resolve(&self, moose: &str) -> impl Future<Output = Result<String, ResolveError>> + Send;
+    /// IRC formatted art of a resolved moose, one line per IRC message.
+    fn irclines(&self, moose: &str) -> impl Future<Output = Result<String, ResolveError>> + Send;
+    /// Search for moose by name; `page` starts at zero.
+    fn search(
+        &self,
+        query: &str,
+        page: usize,
+    ) -> impl Future<Output = Result<SearchPage, ResolveError>> + Send;
+    /// Link to an image of a resolved moose.
+    fn image_url(&self, moose: &str) -> String;
+}
+
+pub enum Backend {
+    Http(Box<HttpBackend>),
+    Local(LocalBackend),
+}
+
+macro_rules! delegate {
+    ($e:expr, $($t:tt)*) => {
+        match $e {
+            $crate::backend::Backend::Http(b) => b.$($t)*,
+            $crate::backend::Backend::Local(b) => b.$($t)*,
+        }
+    };
+}
+
+impl MooseBackend for Backend {
+    async fn resolve(&self, moose: &str) -> Result<String, ResolveError> {
+        delegate!(self, resolve(moose).await)
+    }
+
+    async fn irclines(&self, moose: &str) -> Result<String, ResolveError> {
+        delegate!(self, irclines(moose).await)
+    }
+
+    async fn search(&self, query: &str, page: usize) -> Result<SearchPage, ResolveError> {
+        delegate!(self, search(query, page).await)
+    }
+
+    fn image_url(&self, moose: &str) -> String {
+        delegate!(self, image_url(moose))
+    }
+}
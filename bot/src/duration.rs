@@ -0,0 +1,209 @@
+/* Copyright (C) 2025  Anthony DeDominic
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Write, time::Duration};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// (unit suffix used when formatting, size of unit in nanoseconds)
+/// Ordered largest to smallest.
+const UNITS: &[(&str, u128)] = &[
+    ("d", 86_400 * NANOS_PER_SEC),
+    ("h", 3_600 * NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("s", NANOS_PER_SEC),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+fn unit_nanos(unit: &str) -> Option<u128> {
+    Some(match unit {
+        "d" | "day" | "days" => 86_400 * NANOS_PER_SEC,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3_600 * NANOS_PER_SEC,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60 * NANOS_PER_SEC,
+        "s" | "sec" | "secs" | "second" | "seconds" => NANOS_PER_SEC,
+        "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => 1_000_000,
+        "us" | "µs" | "usec" | "usecs" | "microsecond" | "microseconds" => 1_000,
+        "ns" | "nsec" | "nsecs" | "nanosecond" | "nanoseconds" => 1,
+        _ => return None,
+    })
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("Empty duration is not allowed; please omit or set a value of zero.")]
+    Empty,
+    #[error("Expected a number at `{0}`; durations look like `1h30m`, `1.5s` or `350ms`.")]
+    MissingNumber(String),
+    #[error("`{0}` is not a valid number.")]
+    InvalidNumber(String),
+    #[error(
+        "Number `{0}` is missing a unit; should be one of `d`, `h`, `m`, `s`, `ms`, `us`, `ns`."
+    )]
+    MissingUnit(String),
+    #[error("Invalid duration unit `{0}`; should be one of `d`, `h`, `m`, `s`, `ms`, `us`, `ns`.")]
+    InvalidUnit(String),
+    #[error("Duration `{0}` is too large.")]
+    Overflow(String),
+}
+
+/// Parses a human friendly duration, e.g. `1h30m`, `2min`, `1.5s`, `1m 30s`.
+///
+/// For backwards compatibility, a bare integer without any unit is treated as milliseconds.
+pub fn parse(input: &str) -> Result<Duration, Error> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(Error::Empty);
+    }
+    if input.bytes().all(|b| b.is_ascii_digit()) {
+        let ms = input
+            .parse::<u64>()
+            .map_err(|_| Error::Overflow(input.to_owned()))?;
+        return Ok(Duration::from_millis(ms));
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let num_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (num, tail) = rest.split_at(num_end);
+        if num.is_empty() || num == "." {
+            return Err(Error::MissingNumber(rest.to_owned()));
+        }
+        if num.matches('.').count() > 1 {
+            return Err(Error::InvalidNumber(num.to_owned()));
+        }
+        let tail = tail.trim_start();
+        let unit_end = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+        if unit.is_empty() {
+            return Err(Error::MissingUnit(num.to_owned()));
+        }
+        let unit_ns = unit_nanos(unit).ok_or_else(|| Error::InvalidUnit(unit.to_owned()))?;
+        let nanos =
+            component_nanos(num, unit_ns).ok_or_else(|| Error::Overflow(input.to_owned()))?;
+        total = total
+            .checked_add(nanos)
+            .ok_or_else(|| Error::Overflow(input.to_owned()))?;
+        rest = tail.trim_start();
+    }
+
+    let secs =
+        u64::try_from(total / NANOS_PER_SEC).map_err(|_| Error::Overflow(input.to_owned()))?;
+    Ok(Duration::new(secs, (total % NANOS_PER_SEC) as u32))
+}
+
+/// Converts `<int>[.<frac>]` of a given unit to nanoseconds, truncating anything below 1ns.
+fn component_nanos(num: &str, unit_ns: u128) -> Option<u128> {
+    let (int, frac) = num.split_once('.').unwrap_or((num, ""));
+    let int: u128 = if int.is_empty() { 0 } else { int.parse().ok()? };
+    let mut nanos = int.checked_mul(unit_ns)?;
+    // anything past 18 digits is well below a nanosecond for every unit we support.
+    let frac = &frac[..frac.len().min(18)];
+    if !frac.is_empty() {
+        let scale = 10u128.pow(frac.len() as u32);
+        let frac: u128 = frac.parse().ok()?;
+        nanos = nanos.checked_add(frac * unit_ns / scale)?;
+    }
+    Some(nanos)
+}
+
+/// Formats a duration using the largest units possible, e.g. `1m30s` or `1s500ms`.
+///
+/// The output is always accepted by [`parse`] and round trips exactly.
+pub fn format(dur: &Duration) -> String {
+    let mut rem = dur.as_nanos();
+    if rem == 0 {
+        return "0s".to_owned();
+    }
+    let mut out = String::with_capacity(16);
+    for (suffix, size) in UNITS {
+        let n = rem / size;
+        if n > 0 {
+            let _ = write!(&mut out, "{n}{suffix}");
+            rem %= size;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Error, format, parse};
+
+    #[test]
+    fn parse_durations() {
+        let tests = [
+            ("350", Duration::from_millis(350)),
+            ("350ms", Duration::from_millis(350)),
+            ("10s", Duration::from_secs(10)),
+            ("10 seconds", Duration::from_secs(10)),
+            ("2min", Duration::from_secs(120)),
+            ("1h", Duration::from_secs(3600)),
+            ("1d", Duration::from_secs(86_400)),
+            ("1m30s", Duration::from_secs(90)),
+            ("1m 30s", Duration::from_secs(90)),
+            ("1.5s", Duration::from_millis(1500)),
+            (".5s", Duration::from_millis(500)),
+            ("0.25h", Duration::from_secs(900)),
+            ("1h2m3s4ms5us6ns", Duration::new(3723, 4_005_006)),
+            ("0", Duration::ZERO),
+        ];
+        for (test, expected) in tests {
+            assert_eq!(parse(test), Ok(expected), "parsing {test:?}");
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(""), Err(Error::Empty));
+        assert_eq!(parse("  "), Err(Error::Empty));
+        assert_eq!(parse("s"), Err(Error::MissingNumber("s".to_owned())));
+        assert_eq!(parse("1m30"), Err(Error::MissingUnit("30".to_owned())));
+        assert_eq!(parse("5y"), Err(Error::InvalidUnit("y".to_owned())));
+        assert_eq!(
+            parse("1.2.3s"),
+            Err(Error::InvalidNumber("1.2.3".to_owned()))
+        );
+        assert!(matches!(
+            parse("99999999999999999999999d"),
+            Err(Error::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let tests = [
+            (Duration::ZERO, "0s"),
+            (Duration::from_millis(350), "350ms"),
+            (Duration::from_secs(90), "1m30s"),
+            (Duration::from_millis(1500), "1s500ms"),
+            (Duration::new(90_061, 1), "1d1h1m1s1ns"),
+        ];
+        for (dur, expected) in tests {
+            let formatted = format(&dur);
+            assert_eq!(formatted, expected);
+            assert_eq!(parse(&formatted), Ok(dur));
+        }
+    }
+}
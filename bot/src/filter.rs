@@ -0,0 +1,160 @@
+/* Copyright (C) 2025  Anthony DeDominic
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::borrow::Cow;
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+/// What to do with a line containing blocked text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterAction {
+    /// Replace the blocked text with `*`.
+    #[default]
+    Redact,
+    /// Don't send the line.
+    Skip,
+    /// Don't send the line, and refuse to send any moose containing blocked text.
+    Refuse,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FilterConfig {
+    /// Case insensitive words or phrases.
+    #[serde(default)]
+    pub words: Vec<String>,
+    /// Regular expressions.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub action: FilterAction,
+}
+
+/// Blocklist applied to every line we send, for networks that ban for certain texts (e.g. Rizon).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(try_from = "FilterConfig", into = "FilterConfig")]
+pub struct Filter {
+    config: FilterConfig,
+    regex: Option<Regex>,
+}
+
+impl TryFrom<FilterConfig> for Filter {
+    type Error = regex::Error;
+
+    fn try_from(config: FilterConfig) -> Result<Self, Self::Error> {
+        let mut alts = config
+            .words
+            .iter()
+            .map(|w| format!("(?i:{})", regex::escape(w)))
+            .collect::<Vec<_>>();
+        for pattern in &config.patterns {
+            // compile each pattern on its own so errors point at the offending pattern.
+            Regex::new(pattern)?;
+            alts.push(format!("(?:{pattern})"));
+        }
+        let regex = if alts.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&alts.join("|"))?)
+        };
+        Ok(Self { config, regex })
+    }
+}
+
+impl From<Filter> for FilterConfig {
+    fn from(filter: Filter) -> Self {
+        filter.config
+    }
+}
+
+impl Filter {
+    pub fn action(&self) -> FilterAction {
+        self.config.action
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.as_ref().is_some_and(|r| r.is_match(text))
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match &self.regex {
+            Some(r) => r.replace_all(text, |caps: &Captures| "*".repeat(caps[0].chars().count())),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// Filter a single line; None means the line should not be sent.
+    pub fn line<'a>(&self, text: &'a str) -> Option<Cow<'a, str>> {
+        match self.config.action {
+            FilterAction::Redact => Some(self.redact(text)),
+            FilterAction::Skip | FilterAction::Refuse if self.is_match(text) => None,
+            FilterAction::Skip | FilterAction::Refuse => Some(Cow::Borrowed(text)),
+        }
+    }
+
+    /// Filter all the lines of a moose; None means the whole moose is refused.
+    pub fn moose<'a>(&self, lines: &'a str) -> Option<Vec<Cow<'a, str>>> {
+        if self.config.action == FilterAction::Refuse && self.is_match(lines) {
+            return None;
+        }
+        Some(lines.lines().filter_map(|l| self.line(l)).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, FilterAction, FilterConfig};
+
+    fn filter(action: FilterAction) -> Filter {
+        Filter::try_from(FilterConfig {
+            words: vec!["Bad Word".to_owned()],
+            patterns: vec![r"f[o0]+".to_owned()],
+            action,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn actions() {
+        let f = filter(FilterAction::Redact);
+        assert_eq!(f.line("a bad word, foo").unwrap(), "a ********, ***");
+        assert_eq!(f.line("FOO").unwrap(), "FOO");
+
+        let f = filter(FilterAction::Skip);
+        assert!(f.line("f00").is_none());
+        assert_eq!(f.line("fine").unwrap(), "fine");
+        assert_eq!(f.moose("ok\nfoo\nok").unwrap(), vec!["ok", "ok"]);
+
+        let f = filter(FilterAction::Refuse);
+        assert!(f.moose("ok\nfoo\nok").is_none());
+        assert_eq!(f.moose("ok\nok").unwrap(), vec!["ok", "ok"]);
+
+        let f = Filter::default();
+        assert_eq!(f.line("foo").unwrap(), "foo");
+    }
+
+    #[test]
+    fn bad_pattern() {
+        assert!(
+            Filter::try_from(FilterConfig {
+                patterns: vec!["(".to_owned()],
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
@@ -1,27 +1,149 @@
 use std::sync::Arc;
 
-use irc::proto::{Command, Message, Source, User, command::Numeric};
+use irc::proto::{Command, Message, Source, User, command::Numeric, tags};
 use tokio::sync::{RwLock, mpsc::Sender};
 
 use crate::{
+    backend::MooseBackend,
     debug,
-    helpers::{CONFLICT_FILLER, join_channels},
+    helpers::{CONFLICT_FILLER, WANTED_CAPS, join_channels},
     tasks::{invite::InviteMsg, sender},
-    webreq::{get_irclines, get_search, resolve_moosename},
 };
 
 use super::{
     ircstate::{APP_NAME, IrcState},
-    moosecmd::{HELP_RESP, MComm, parse_moose_args},
+    members::Members,
+    moosecmd::{Format, MComm, MooseArgs, Target, parse_moose_args},
+    search::search,
 };
 
+/// Whether the sender of a command in channel may have a moose sent to nick.
+///
+/// Users only send to others in the channel, so the bot can't be used to pester anyone else.
+fn check_to(
+    state: &IrcState,
+    nick: &str,
+    channel: &str,
+    account: Option<&str>,
+) -> Result<(), String> {
+    if nick.eq_ignore_ascii_case(&state.current_nick) {
+        Err("I won't send a moose to myself.".to_owned())
+    } else if state.members.contains(channel, nick)
+        || account.is_some_and(|a| state.trusted_accounts.contains(a))
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "Option `--to` only sends to users in this channel, and {nick} is not."
+        ))
+    }
+}
+
+/// Who receives a moose asked for in reply, letting other users know who sent it.
+fn destination(to: Target, reply: &str, sender: &str, sendo: &sender::Sender) -> String {
+    match to {
+        Target::Reply => reply.to_owned(),
+        Target::Private => sender.to_owned(),
+        Target::Nick(nick) => {
+            sendo.lossy_send(
+                Command::NOTICE(nick.clone(), format!("{sender} sent you a moose.")).into(),
+            );
+            nick
+        }
+    }
+}
+
+/// Negotiate capabilities during registration.
+///
+/// `CAP LS` may span several messages, so this must see them in order rather than in
+/// concurrently spawned handlers.
+pub async fn negotiate(state: &RwLock<IrcState>, command: Command, sendo: &sender::Sender) {
+    let Command::CAP(_, sub, a, b) = command else {
+        return;
+    };
+    match sub.as_str() {
+        "LS" => {
+            // `CAP * LS * :caps` means more caps follow.
+            let (more, caps) = match (a, b) {
+                (Some(more), Some(caps)) if more == "*" => (true, caps),
+                (Some(caps), _) => (false, caps),
+                _ => (false, String::new()),
+            };
+            let mut wstate = state.write().await;
+            wstate.offered_caps.extend(
+                caps.split_ascii_whitespace()
+                    .map(|c| c.split_once('=').map_or(c, |(c, _)| c).to_owned()),
+            );
+            if more {
+                return;
+            }
+            let req = WANTED_CAPS
+                .iter()
+                .filter(|c| wstate.offered_caps.contains(**c))
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let cap = if req.is_empty() {
+                Command::CAP(None, "END".to_owned(), None, None)
+            } else {
+                Command::CAP(None, "REQ".to_owned(), Some(req), None)
+            };
+            sendo.send(cap.into()).await;
+        }
+        "ACK" => {
+            let caps = a.unwrap_or_default();
+            eprintln!("INFO: [irc] Enabled capabilities: {caps}");
+            let mut wstate = state.write().await;
+            wstate.caps.extend(
+                caps.split_ascii_whitespace()
+                    .filter(|c| !c.starts_with('-'))
+                    .map(str::to_owned),
+            );
+            if wstate.caps.contains("labeled-response") {
+                sendo.enable_labels();
+            }
+            sendo
+                .send(Command::CAP(None, "END".to_owned(), None, None).into())
+                .await;
+        }
+        "NAK" => {
+            sendo
+                .send(Command::CAP(None, "END".to_owned(), None, None).into())
+                .await;
+        }
+        _ => (),
+    }
+}
+
+/// Follow who is in our channels.
+///
+/// A JOIN has to be seen before the NAMES reply that follows it, so this must see
+/// messages in order rather than in concurrently spawned handlers.
+pub async fn track(state: &RwLock<IrcState>, msg: &Message) {
+    if !Members::tracks(&msg.command) {
+        return;
+    }
+    let sender = match &msg.source {
+        Some(Source::Server(server)) => server,
+        Some(Source::User(User { nickname, .. })) => nickname,
+        None => "",
+    };
+    let mut wstate = state.write().await;
+    let me = wstate.current_nick.clone();
+    wstate.members.update(&me, sender, &msg.command);
+}
+
 pub async fn handle(
     state: Arc<RwLock<IrcState>>,
     msg: Message,
     disable_search: bool,
+    stale: bool,
     sendo: sender::Sender,
     sendi: Sender<InviteMsg>,
 ) {
+    let label = msg.label().map(str::to_owned);
+    let account = msg.account().map(str::to_owned);
+    let msgid = msg.msgid().map(str::to_owned);
     let sender = match msg.source {
         Some(Source::Server(server)) => server,
         Some(Source::User(User { nickname, .. })) => nickname,
@@ -45,6 +167,20 @@ pub async fn handle(
             eprintln!("INFO: [irc] Parted {channel}");
             let _ = sendi.send(InviteMsg::Kicked(channel)).await;
         }
+        Command::INVITE(target, _)
+            if rstate.current_nick == target
+                && !rstate.trusted_accounts.is_empty()
+                && !account
+                    .as_ref()
+                    .is_some_and(|a| rstate.trusted_accounts.contains(a)) =>
+        {
+            sendo
+                .send(
+                    Command::NOTICE(sender, "Only trusted accounts may invite me.".to_owned())
+                        .into(),
+                )
+                .await;
+        }
         Command::INVITE(target, channel) if rstate.current_nick == target => {
             if sendi.try_send(InviteMsg::Joined(channel.clone())).is_ok() {
                 sendo.send(Command::JOIN(channel, None).into()).await;
@@ -61,6 +197,15 @@ pub async fn handle(
             );
             let _ = sendi.send(InviteMsg::Kicked(channel)).await;
         }
+        // echo-message sends our own messages back to us.
+        Command::PRIVMSG(..) | Command::NOTICE(..) if rstate.current_nick == sender => {
+            if let Some(label) = label {
+                sendo.echoed(&label);
+            }
+        }
+        Command::PRIVMSG(..) if stale => {
+            eprintln!("INFO: [irc] Ignoring replayed message from {sender}.");
+        }
         Command::PRIVMSG(channel, msg)
             if rstate.current_nick == channel && msg == "\x01VERSION\x01" =>
         {
@@ -68,59 +213,124 @@ pub async fn handle(
                 .send(Command::NOTICE(sender, format!("\x01VERSION {APP_NAME}\x01")).into())
                 .await;
         }
-        Command::PRIVMSG(channel, msg) => {
-            if let Some(comm) = parse_moose_args(&msg) {
+        Command::PRIVMSG(target, msg) => {
+            // reply to private messages privately.
+            let channel = if rstate.current_nick == target {
+                sender.clone()
+            } else {
+                target
+            };
+            let mut reply_to = channel.clone();
+            // thread replies to the command, when the server lets us tag messages.
+            let thread = |m: Message| match &msgid {
+                Some(id) if rstate.caps.contains("message-tags") => m.with_tag(tags::REPLY, id),
+                _ => m,
+            };
+            if let Some(comm) = parse_moose_args(&msg, &rstate.commands, &rstate.current_nick) {
+                let comm = match comm {
+                    MComm::Moose(MooseArgs {
+                        to: Target::Nick(ref nick),
+                        ..
+                    }) => match check_to(&rstate, nick, &channel, account.as_deref()) {
+                        Ok(()) => comm,
+                        Err(e) => MComm::Invalid(e),
+                    },
+                    comm => comm,
+                };
                 let resp = match comm {
-                    MComm::Help => HELP_RESP.to_owned(),
+                    MComm::Help(topic) => topic.text(rstate.commands.prefix()),
+                    MComm::Invalid(err) => err,
+                    MComm::Stop if sendo.stop_moose(&channel) => "Stopped the moose.".to_owned(),
+                    MComm::Stop => "There is no moose to stop.".to_owned(),
                     MComm::Bots => format!(
-                        "Moose :: Make moose @ {} :: See .moose --help for usage",
-                        rstate.moose_url
+                        "Moose :: Make moose @ {} :: See {}moose --help for usage",
+                        rstate.moose_url,
+                        rstate.commands.prefix()
                     ),
                     MComm::Search(q) if disable_search => format!(
                         "Search has been disabled on this server. See: {}/gallery/0?q={}",
                         rstate.moose_url,
                         percent_encoding::percent_encode(
-                            q.as_bytes(),
+                            q.query.as_bytes(),
                             percent_encoding::NON_ALPHANUMERIC
                         )
                     ),
-                    MComm::Search(q) => get_search(&rstate.moose_client, &rstate.moose_url, &q)
+                    MComm::SearchNext if disable_search => {
+                        "Search has been disabled on this server.".to_owned()
+                    }
+                    MComm::Search(_) | MComm::SearchNext => {
+                        let query = match comm {
+                            MComm::Search(q) => Some(q),
+                            _ => None,
+                        };
+                        match search(
+                            &rstate.moose,
+                            &rstate.filter,
+                            &rstate.search_cursors,
+                            &sender,
+                            query,
+                            &channel,
+                            rstate.commands.prefix(),
+                        )
                         .await
-                        .unwrap_or_else(|e| e.to_string()),
-                    MComm::Image(q) => {
-                        match resolve_moosename(&rstate.moose_client, &rstate.moose_url, &q).await {
-                            Ok(moose) => format!("{}/img/{}", &rstate.moose_url, &moose),
+                        {
+                            Ok(lines) => {
+                                for line in lines {
+                                    sendo
+                                        .send(thread(
+                                            Command::PRIVMSG(channel.clone(), line).into(),
+                                        ))
+                                        .await;
+                                }
+                                return;
+                            }
                             Err(e) => e.to_string(),
                         }
                     }
-                    MComm::Irc(q) => {
-                        match resolve_moosename(&rstate.moose_client, &rstate.moose_url, &q).await {
+                    MComm::Moose(MooseArgs {
+                        name,
+                        format: Format::Image,
+                        to,
+                    }) => match rstate.moose.resolve(&name).await {
+                        Ok(moose) => {
+                            reply_to = destination(to, &channel, &sender, &sendo);
+                            rstate.moose.image_url(&moose)
+                        }
+                        Err(e) => e.to_string(),
+                    },
+                    MComm::Moose(MooseArgs {
+                        name,
+                        format: Format::Irc,
+                        to,
+                    }) => {
+                        match rstate.moose.resolve(&name).await {
                             Ok(moose) => {
                                 // TODO: fix this crap.
                                 match rstate.moose_delay.check() {
-                                    Ok(_) => {
-                                        match get_irclines(
-                                            &rstate.moose_client,
-                                            &rstate.moose_url,
-                                            &moose,
-                                        )
-                                        .await
-                                        {
-                                            Ok(lines) => {
-                                                lines.lines().for_each(|line| {
-                                                    sendo.send_moose(
+                                    Ok(_) => match rstate.moose.irclines(&moose).await {
+                                        Ok(lines) => match rstate.filter.moose(&lines) {
+                                            Some(lines) => {
+                                                let dest =
+                                                    destination(to, &channel, &sender, &sendo);
+                                                let lines = lines
+                                                    .into_iter()
+                                                    .map(|line| {
                                                         Command::PRIVMSG(
-                                                            channel.clone(),
-                                                            line.to_owned(),
+                                                            dest.clone(),
+                                                            line.into_owned(),
                                                         )
-                                                        .into(),
-                                                    )
-                                                });
-                                                return;
+                                                        .into()
+                                                    })
+                                                    .collect();
+                                                match sendo.send_moose(&dest, &sender, lines) {
+                                                    Ok(()) => return,
+                                                    Err(e) => e.to_string(),
+                                                }
                                             }
-                                            Err(e) => e.to_string(),
-                                        }
-                                    }
+                                            None => "That moose cannot be shown here.".to_owned(),
+                                        },
+                                        Err(e) => e.to_string(),
+                                    },
                                     Err(retry_after) => {
                                         let plural = if retry_after != 1 { "s" } else { "" };
                                         sendo.lossy_send(
@@ -138,10 +348,30 @@ pub async fn handle(
                         }
                     }
                 };
-                sendo.send(Command::PRIVMSG(channel, resp).into()).await;
+                if let Some(resp) = rstate.filter.line(&resp) {
+                    sendo
+                        .send(thread(Command::PRIVMSG(reply_to, resp.into_owned()).into()))
+                        .await;
+                }
             }
         }
-        Command::Numeric(num, _params) => match num {
+        Command::Numeric(num, params) => match num {
+            Numeric::ERR_CANNOTSENDTOCHAN => {
+                let channel = params.get(1).map_or("", String::as_str);
+                let reason = params.get(2).map_or("", String::as_str);
+                eprintln!("WARN: [irc] Cannot send to {channel}: {reason}");
+                if let Some(requester) = sendo.rejected(label.as_deref(), channel) {
+                    sendo
+                        .send(
+                            Command::NOTICE(
+                                requester,
+                                format!("I can't send moose to {channel}: {reason}"),
+                            )
+                            .into(),
+                        )
+                        .await;
+                }
+            }
             Numeric::RPL_WELCOME => {
                 if let Some(ref npass) = rstate.nickserv_pass {
                     sendo
@@ -173,3 +403,316 @@ pub async fn handle(
         _ => (),
     };
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+
+    use irc::proto::{Command, Message, Source, User, command::Numeric};
+    use tokio::sync::{RwLock, mpsc};
+
+    use crate::{
+        backend::{Backend, LocalBackend},
+        filter::Filter,
+        handlers::{ircstate::IrcState, moosecmd::Commands},
+        helpers::TempDir,
+        tasks::sender::create_send_recv_pair,
+    };
+
+    fn privmsg(text: &str) -> Message {
+        privmsg_to("#moose", text)
+    }
+
+    fn privmsg_to(target: &str, text: &str) -> Message {
+        Message {
+            tags: vec![],
+            source: Some(Source::User(User {
+                nickname: "someone".to_owned(),
+                username: Some("u".to_owned()),
+                hostname: Some("localhost".to_owned()),
+            })),
+            command: Command::PRIVMSG(target.to_owned(), text.to_owned()),
+            raw: None,
+        }
+    }
+
+    async fn run(state: &Arc<RwLock<IrcState>>, text: &str) -> Vec<Message> {
+        run_msg(state, privmsg(text)).await
+    }
+
+    async fn run_msg(state: &Arc<RwLock<IrcState>>, msg: Message) -> Vec<Message> {
+        let (sendo, mut recvo) = create_send_recv_pair();
+        let (sendi, _recvi) = mpsc::channel(1);
+        super::handle(state.clone(), msg, false, false, sendo, sendi).await;
+        recvo.drain()
+    }
+
+    fn reply(text: &str) -> Message {
+        Command::PRIVMSG("#moose".to_owned(), text.to_owned()).into()
+    }
+
+    /// state serving the given moose names from a temporary directory.
+    fn local_state(moose: &[&str]) -> (Arc<RwLock<IrcState>>, TempDir) {
+        let dir = TempDir::new("moose-irc2-test");
+        for name in moose {
+            std::fs::write(dir.0.join(format!("{name}.irc")), "line 1\nline 2\n").unwrap();
+        }
+        let state = Arc::new(RwLock::new(IrcState::new(
+            "MrMoose".to_owned(),
+            None,
+            HashSet::new(),
+            "https://moose.invalid".to_owned(),
+            Duration::ZERO,
+            Backend::Local(LocalBackend::new(
+                dir.0.clone(),
+                "https://moose.invalid".to_owned(),
+            )),
+            Filter::default(),
+            Commands::default(),
+        )));
+        (state, dir)
+    }
+
+    #[tokio::test]
+    async fn local_backend() {
+        let (state, _dir) = local_state(&["test%20moose"]);
+
+        assert_eq!(
+            run(&state, ".moose test moose").await,
+            vec![reply("line 1"), reply("line 2")]
+        );
+        assert_eq!(
+            run(&state, ".moose").await,
+            vec![reply("line 1"), reply("line 2")]
+        );
+        assert_eq!(
+            run(&state, ".mooseimg test moose").await,
+            vec![reply("https://moose.invalid/img/test%20moose")]
+        );
+        assert_eq!(
+            run(&state, ".moosesearch TEST").await,
+            vec![reply("\u{2}test moose\u{2} p.0")]
+        );
+        assert_eq!(
+            run(&state, ".moose nope").await,
+            vec![reply("No such moose: nope")]
+        );
+        assert_eq!(
+            run(&state, ".moose -i \"test moose\"").await,
+            vec![reply("https://moose.invalid/img/test%20moose")]
+        );
+        assert_eq!(
+            run(&state, ".moose --bogus").await,
+            vec![reply("Unknown option `--bogus`; see .moose --help")]
+        );
+    }
+
+    #[tokio::test]
+    async fn search_next() {
+        let (state, _dir) = local_state(&["moose%20a", "moose%20b", "moose%20c"]);
+
+        assert_eq!(
+            run(&state, ".moosesearch --limit 2 moose").await,
+            vec![reply(
+                "\u{2}moose a\u{2} p.0, \u{2}moose b\u{2} p.0, (page 1/1, 3 results; .moosesearch --next for more)"
+            )]
+        );
+        assert_eq!(
+            run(&state, ".moosesearch --next").await,
+            vec![reply("\u{2}moose c\u{2} p.0, (page 1/1, 3 results)")]
+        );
+        assert_eq!(
+            run(&state, ".moosesearch --next").await,
+            vec![reply("Error: No search to continue.")]
+        );
+        assert_eq!(
+            run(&state, ".moosesearch --page 2 moose").await,
+            vec![reply("Error: No more results.")]
+        );
+    }
+
+    #[tokio::test]
+    async fn private_replies() {
+        let (state, _dir) = local_state(&["test%20moose"]);
+        let to = |target: &str, text: &str| -> Message {
+            Command::PRIVMSG(target.to_owned(), text.to_owned()).into()
+        };
+
+        assert_eq!(
+            run_msg(&state, privmsg_to("MrMoose", ".moose test moose")).await,
+            vec![to("someone", "line 1"), to("someone", "line 2")]
+        );
+        assert_eq!(
+            run(&state, ".moose --private test moose").await,
+            vec![to("someone", "line 1"), to("someone", "line 2")]
+        );
+        assert_eq!(
+            run(&state, ".mooseimg --to=other test moose").await,
+            vec![reply(
+                "Option `--to` only sends to users in this channel, and other is not."
+            )]
+        );
+        assert_eq!(
+            run(&state, ".moose --to=mrmoose test moose").await,
+            vec![reply("I won't send a moose to myself.")]
+        );
+        let names = Command::Numeric(
+            Numeric::RPL_NAMREPLY,
+            ["MrMoose", "=", "#moose", "@MrMoose +other"]
+                .map(str::to_owned)
+                .to_vec(),
+        );
+        for command in [Command::JOIN("#moose".to_owned(), None), names] {
+            let mut msg = privmsg("");
+            msg.command = command;
+            msg.source = Some(Source::User(User {
+                nickname: "MrMoose".to_owned(),
+                username: None,
+                hostname: None,
+            }));
+            super::track(&state, &msg).await;
+        }
+        assert_eq!(
+            run(&state, ".mooseimg --to=other test moose").await,
+            vec![
+                Command::NOTICE("other".to_owned(), "someone sent you a moose.".to_owned()).into(),
+                to("other", "https://moose.invalid/img/test%20moose")
+            ]
+        );
+        // trusted accounts may send anywhere.
+        state
+            .write()
+            .await
+            .trusted_accounts
+            .insert("moose".to_owned());
+        let msg = privmsg_to("MrMoose", ".mooseimg --to=elsewhere test moose")
+            .with_tag(irc::proto::tags::ACCOUNT, "moose");
+        assert_eq!(
+            run_msg(&state, msg).await,
+            vec![
+                Command::NOTICE(
+                    "elsewhere".to_owned(),
+                    "someone sent you a moose.".to_owned()
+                )
+                .into(),
+                to("elsewhere", "https://moose.invalid/img/test%20moose")
+            ]
+        );
+        assert_eq!(
+            run(&state, ".moose --private nope").await,
+            vec![reply("No such moose: nope")]
+        );
+    }
+
+    #[tokio::test]
+    async fn capabilities() {
+        let (state, _dir) = local_state(&["test%20moose"]);
+        let negotiate = |state: &Arc<RwLock<IrcState>>, msg: Message| {
+            let state = state.clone();
+            async move {
+                let (sendo, mut recvo) = create_send_recv_pair();
+                super::negotiate(&state, msg.command, &sendo).await;
+                recvo.drain()
+            }
+        };
+        let cap = |a: Option<&str>, sub: &str, b: Option<&str>, c: Option<&str>| -> Message {
+            Command::CAP(
+                a.map(str::to_owned),
+                sub.to_owned(),
+                b.map(str::to_owned),
+                c.map(str::to_owned),
+            )
+            .into()
+        };
+
+        assert_eq!(
+            negotiate(
+                &state,
+                cap(Some("*"), "LS", Some("*"), Some("sasl echo-message"))
+            )
+            .await,
+            vec![]
+        );
+        assert_eq!(
+            negotiate(
+                &state,
+                cap(Some("*"), "LS", Some("labeled-response batch=x"), None)
+            )
+            .await,
+            vec![cap(
+                None,
+                "REQ",
+                Some("echo-message labeled-response batch"),
+                None
+            )]
+        );
+        assert_eq!(
+            negotiate(
+                &state,
+                cap(
+                    Some("*"),
+                    "ACK",
+                    Some("echo-message labeled-response"),
+                    None
+                )
+            )
+            .await,
+            vec![cap(None, "END", None, None)]
+        );
+        assert!(state.read().await.caps.contains("echo-message"));
+
+        // our own moose, echoed back, is not a command.
+        let mut echo = privmsg(".moose test moose");
+        echo.source = Some(Source::User(User {
+            nickname: "MrMoose".to_owned(),
+            username: None,
+            hostname: None,
+        }));
+        assert_eq!(run_msg(&state, echo).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        let (state, _dir) = local_state(&["test%20moose"]);
+        state.write().await.caps.insert("message-tags".to_owned());
+
+        let msg = privmsg(".mooseimg test moose").with_tag(irc::proto::tags::MSGID, "abc");
+        assert_eq!(
+            run_msg(&state, msg).await,
+            vec![
+                reply("https://moose.invalid/img/test%20moose")
+                    .with_tag(irc::proto::tags::REPLY, "abc")
+            ]
+        );
+
+        state
+            .write()
+            .await
+            .trusted_accounts
+            .insert("moose".to_owned());
+        let invite = |account: Option<&str>| {
+            let msg = Message {
+                command: Command::INVITE("MrMoose".to_owned(), "#new".to_owned()),
+                ..privmsg("")
+            };
+            match account {
+                Some(a) => msg.with_tag(irc::proto::tags::ACCOUNT, a),
+                None => msg,
+            }
+        };
+        assert_eq!(
+            run_msg(&state, invite(None)).await,
+            vec![
+                Command::NOTICE(
+                    "someone".to_owned(),
+                    "Only trusted accounts may invite me.".to_owned()
+                )
+                .into()
+            ]
+        );
+        assert_eq!(
+            run_msg(&state, invite(Some("moose"))).await,
+            vec![Command::JOIN("#new".to_owned(), None).into()]
+        );
+    }
+}
@@ -6,6 +6,10 @@ use governor::{
     state::{InMemoryState, NotKeyed},
 };
 
+use crate::{backend::Backend, filter::Filter};
+
+use super::{members::Members, moosecmd::Commands, search::SearchCursors};
+
 pub const APP_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 pub enum MooseLim {
@@ -36,17 +40,32 @@ pub struct IrcState {
     pub nickserv_pass: Option<String>,
     pub channels: HashSet<String>,
     pub moose_url: String,
-    pub moose_client: reqwest::Client,
+    pub moose: Backend,
     pub moose_delay: MooseLim,
+    pub search_cursors: SearchCursors,
+    pub filter: Filter,
+    pub commands: Commands,
+    /// capabilities the server offered in `CAP LS`.
+    pub offered_caps: HashSet<String>,
+    /// capabilities the server acknowledged.
+    pub caps: HashSet<String>,
+    /// services accounts allowed to invite us, anyone may when empty,
+    /// and to send moose to users outside the channel.
+    pub trusted_accounts: HashSet<String>,
+    pub members: Members,
 }
 
 impl IrcState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         nick: String,
         nickserv_pass: Option<String>,
         channels: HashSet<String>,
         moose_url: String,
         moose_delay: Duration,
+        moose: Backend,
+        filter: Filter,
+        commands: Commands,
     ) -> Self {
         let moose_delay = if moose_delay.is_zero() {
             MooseLim::None
@@ -57,19 +76,21 @@ impl IrcState {
                     .allow_burst(NonZero::<u32>::new(1).unwrap()),
             ))
         };
-        let moose_client = reqwest::Client::builder()
-            .user_agent(APP_NAME)
-            .timeout(Duration::from_secs(5))
-            .build()
-            .expect("FATAL: [irc] Expected to build HTTP client.");
         Self {
             original_nick: nick.clone(),
             current_nick: nick,
             nickserv_pass,
             channels,
             moose_url,
-            moose_client,
+            moose,
             moose_delay,
+            search_cursors: SearchCursors::default(),
+            filter,
+            commands,
+            offered_caps: HashSet::new(),
+            caps: HashSet::new(),
+            trusted_accounts: HashSet::new(),
+            members: Members::default(),
         }
     }
 }
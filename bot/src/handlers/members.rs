@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use irc::proto::{Command, command::Numeric};
+
+/// Membership prefixes servers put before nicks in `NAMES` replies, e.g. `@` for operators.
+const MEMBER_PREFIXES: &[char] = &['~', '&', '@', '%', '+'];
+
+/// Who is in the channels we are in, by lowercase channel and nick.
+#[derive(Debug, Default)]
+pub struct Members(HashMap<String, HashSet<String>>);
+
+impl Members {
+    /// Whether command changes who is in our channels.
+    pub fn tracks(command: &Command) -> bool {
+        matches!(
+            command,
+            Command::JOIN(..)
+                | Command::PART(..)
+                | Command::KICK(..)
+                | Command::QUIT(..)
+                | Command::NICK(..)
+                | Command::Numeric(Numeric::RPL_NAMREPLY, _)
+        )
+    }
+
+    /// Follow a command sender sent; me is our current nick.
+    pub fn update(&mut self, me: &str, sender: &str, command: &Command) {
+        let sender = sender.to_lowercase();
+        match command {
+            Command::JOIN(channels, _) => {
+                for channel in channels.split(',').map(str::to_lowercase) {
+                    // whoever is there already follows in NAMES.
+                    if sender == me.to_lowercase() {
+                        self.0.insert(channel.clone(), HashSet::new());
+                    }
+                    if let Some(members) = self.0.get_mut(&channel) {
+                        members.insert(sender.clone());
+                    }
+                }
+            }
+            Command::PART(channels, _) => {
+                for channel in channels.split(',').map(str::to_lowercase) {
+                    self.leave(me, &channel, &sender);
+                }
+            }
+            Command::KICK(channel, target, _) => {
+                self.leave(me, &channel.to_lowercase(), &target.to_lowercase());
+            }
+            Command::QUIT(_) => {
+                for members in self.0.values_mut() {
+                    members.remove(&sender);
+                }
+            }
+            Command::NICK(nick) => {
+                for members in self.0.values_mut() {
+                    if members.remove(&sender) {
+                        members.insert(nick.to_lowercase());
+                    }
+                }
+            }
+            // `353 me = #channel :@op +voice nick!user@host`
+            Command::Numeric(Numeric::RPL_NAMREPLY, params) if params.len() > 3 => {
+                let Some(members) = self.0.get_mut(&params[2].to_lowercase()) else {
+                    return;
+                };
+                let nicks = params[3].split_ascii_whitespace().map(|n| {
+                    let n = n.trim_start_matches(MEMBER_PREFIXES);
+                    n.split_once('!').map_or(n, |(nick, _)| nick).to_lowercase()
+                });
+                members.extend(nicks);
+            }
+            _ => (),
+        }
+    }
+
+    fn leave(&mut self, me: &str, channel: &str, nick: &str) {
+        if nick == me.to_lowercase() {
+            self.0.remove(channel);
+        } else if let Some(members) = self.0.get_mut(channel) {
+            members.remove(nick);
+        }
+    }
+
+    pub fn contains(&self, channel: &str, nick: &str) -> bool {
+        self.0
+            .get(&channel.to_lowercase())
+            .is_some_and(|m| m.contains(&nick.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use irc::proto::{Command, command::Numeric};
+
+    use super::Members;
+
+    #[test]
+    fn follows_channels() {
+        let mut members = Members::default();
+        let names = Command::Numeric(
+            Numeric::RPL_NAMREPLY,
+            ["MrMoose", "=", "#Moose", "@Op +voice MrMoose guest!u@h"]
+                .map(str::to_owned)
+                .to_vec(),
+        );
+        let join = |c: &str| Command::JOIN(c.to_owned(), None);
+        members.update("MrMoose", "someone", &join("#moose"));
+        assert!(!members.contains("#moose", "someone"));
+
+        members.update("MrMoose", "MrMoose", &join("#moose"));
+        members.update("MrMoose", "irc.host", &names);
+        members.update("MrMoose", "someone", &join("#moose"));
+        for nick in ["op", "Voice", "guest", "someone", "MrMoose"] {
+            assert!(members.contains("#MOOSE", nick), "{nick}");
+        }
+
+        members.update("MrMoose", "guest", &Command::NICK("host".to_owned()));
+        assert!(!members.contains("#moose", "guest"));
+        assert!(members.contains("#moose", "host"));
+        members.update("MrMoose", "op", &Command::QUIT(None));
+        members.update(
+            "MrMoose",
+            "someone",
+            &Command::PART("#moose".to_owned(), None),
+        );
+        let kick = Command::KICK("#moose".to_owned(), "voice".to_owned(), None);
+        members.update("MrMoose", "host", &kick);
+        for nick in ["op", "someone", "voice"] {
+            assert!(!members.contains("#moose", nick), "{nick}");
+        }
+
+        members.update(
+            "MrMoose",
+            "MrMoose",
+            &Command::PART("#moose".to_owned(), None),
+        );
+        assert!(!members.contains("#moose", "host"));
+    }
+}
@@ -1,3 +1,5 @@
 pub mod handler;
 pub mod ircstate;
+pub mod members;
 pub mod moosecmd;
+pub mod search;
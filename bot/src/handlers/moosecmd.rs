@@ -1,74 +1,662 @@
-use crate::debug;
+use std::{borrow::Cow, collections::HashMap};
 
-pub const HELP_RESP: &str =
-    "usage: ^[.!]?moose(?:img|search|me)? [--latest|--random|--search|--image|--] [moosename]";
+use serde::{Deserialize, Serialize};
 
-pub enum MComm {
-    Help,
-    Bots,
-    Search(String),
-    Image(String),
-    Irc(String),
+use crate::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelpTopic {
+    General,
+    Moose,
+    Image,
+    Search,
 }
 
-impl<'a> From<(PComm, &'a str)> for MComm {
-    fn from(value: (PComm, &'a str)) -> Self {
-        debug!("DEBUG: CMD PARSED {value:?}");
-        let m = value.1.to_owned();
-        match value.0 {
-            PComm::Search => Self::Search(m),
-            PComm::Image => Self::Image(m),
-            PComm::Irc => Self::Irc(m),
+impl HelpTopic {
+    /// Help for commands starting with prefix, e.g. `.`.
+    pub fn text(self, p: &str) -> String {
+        match self {
+            HelpTopic::General => format!(
+                "usage: {p}moose(?:img|search|me)? [options] [moosename] :: see {p}moose --help, {p}mooseimg --help or {p}moosesearch --help"
+            ),
+            HelpTopic::Moose => format!(
+                "usage: {p}moose [-r|--random] [-l|--latest] [-o|--oldest] [-i|--image] [-s|--search] [-f|--format=irc|image] [-t|--to=nick] [--private] [--stop] [--] [moosename] :: --stop cancels moose being sent here; put -- before names that look like options, e.g. {p}moose -- --weird moose"
+            ),
+            HelpTopic::Image => format!(
+                "usage: {p}mooseimg [-r|--random] [-l|--latest] [-o|--oldest] [-t|--to=nick] [--private] [--] [moosename] :: --private sends it to you instead of the channel"
+            ),
+            HelpTopic::Search => format!(
+                "usage: {p}moosesearch [-p|--page=N] [--limit=N] [-n|--next] [--] query :: -n continues your last search"
+            ),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Irc,
+    Image,
+}
+
+/// Who should receive a moose.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Target {
+    /// where the command came from; the sender, for private messages.
+    #[default]
+    Reply,
+    /// the sender, even when asked in a channel.
+    Private,
+    /// another user.
+    Nick(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MooseArgs {
+    /// moose name, or one of `random`, `latest`, `oldest`.
+    pub name: String,
+    pub format: Format,
+    pub to: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub query: String,
+    /// page of results, starting at zero.
+    pub page: usize,
+    /// how many results to show at once; defaults to the whole page.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MComm {
+    Help(HelpTopic),
+    Bots,
+    Moose(MooseArgs),
+    Search(SearchQuery),
+    /// continue the user's last search.
+    SearchNext,
+    /// cancel moose being sent where this was asked.
+    Stop,
+    /// the command was malformed; contains a message for the user.
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum PComm {
     Search,
     Image,
     Irc,
 }
 
+impl PComm {
+    fn topic(self) -> HelpTopic {
+        match self {
+            PComm::Search => HelpTopic::Search,
+            PComm::Image => HelpTopic::Image,
+            PComm::Irc => HelpTopic::Moose,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Token<'a> {
+    text: Cow<'a, str>,
+    /// quoted tokens are never options.
+    quoted: bool,
+    /// what follows the token in the input, untouched.
+    after: &'a str,
+}
+
+/// Split on whitespace; a token starting with `"` runs to the closing quote, inside of which
+/// a backslash escapes the next character. Anything else, like `bob's` or `a\b`, is literal.
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { rest: input }
+    }
+
+    /// The input from the next token on, untouched.
+    fn rest(&self) -> &'a str {
+        self.rest.trim_matches(ws)
+    }
+
+    fn next(&mut self) -> Result<Option<Token<'a>>, String> {
+        let input = self.rest.trim_start_matches(ws);
+        let Some(quoted) = input.strip_prefix('"') else {
+            if input.is_empty() {
+                return Ok(None);
+            }
+            let (text, after) = input.split_at(input.find(ws).unwrap_or(input.len()));
+            self.rest = after;
+            return Ok(Some(Token {
+                text: Cow::Borrowed(text),
+                quoted: false,
+                after,
+            }));
+        };
+        let mut text = String::new();
+        let mut chars = quoted.char_indices();
+        loop {
+            match chars.next() {
+                Some((i, '"')) => {
+                    let after = &quoted[i + 1..];
+                    self.rest = after;
+                    return Ok(Some(Token {
+                        text: Cow::Owned(text),
+                        quoted: true,
+                        after,
+                    }));
+                }
+                Some((_, '\\')) => text.extend(chars.next().map(|(_, c)| c)),
+                Some((_, c)) => text.push(c),
+                None => return Err("Missing closing \" quote.".to_owned()),
+            }
+        }
+    }
+}
+
+fn parse_num(opt: &str, value: Option<String>) -> Result<usize, String> {
+    let value = value.ok_or_else(|| format!("Option `{opt}` requires a number."))?;
+    value
+        .parse()
+        .map_err(|_| format!("Option `{opt}` requires a number, not `{value}`."))
+}
+
+#[derive(Default)]
+struct Opts {
+    help: bool,
+    selector: Option<&'static str>,
+    comm: Option<PComm>,
+    format: Option<Format>,
+    page: Option<usize>,
+    limit: Option<usize>,
+    next: bool,
+    to: Option<Target>,
+    stop: bool,
+}
+
+const CHANNEL_PREFIXES: [char; 4] = ['#', '&', '+', '!'];
+
+/// Whether the option takes a value; long and short names map to the long name.
+fn option_spec(opt: &str) -> Option<(&'static str, bool)> {
+    Some(match opt {
+        "h" | "help" => ("help", false),
+        "r" | "random" => ("random", false),
+        "l" | "latest" => ("latest", false),
+        "o" | "oldest" => ("oldest", false),
+        "i" | "image" => ("image", false),
+        "s" | "search" => ("search", false),
+        "n" | "next" => ("next", false),
+        "f" | "format" => ("format", true),
+        "p" | "page" => ("page", true),
+        "limit" => ("limit", true),
+        "t" | "to" => ("to", true),
+        "private" => ("private", false),
+        "stop" => ("stop", false),
+        _ => return None,
+    })
+}
+
+impl Opts {
+    fn set(&mut self, opt: &'static str, value: Option<String>) -> Result<(), String> {
+        match opt {
+            "help" => self.help = true,
+            "random" | "latest" | "oldest" => self.selector = Some(opt),
+            "image" => self.comm = Some(PComm::Image),
+            "search" => self.comm = Some(PComm::Search),
+            "next" => self.next = true,
+            "format" => {
+                self.format = Some(match value.as_deref() {
+                    Some("irc") => Format::Irc,
+                    Some("image" | "img") => Format::Image,
+                    _ => {
+                        return Err("Option `--format` must be one of `irc` or `image`.".to_owned());
+                    }
+                })
+            }
+            // pages are shown to users starting at one.
+            "page" => self.page = Some(parse_num("--page", value)?.saturating_sub(1)),
+            "limit" => self.limit = Some(parse_num("--limit", value)?.max(1)),
+            "to" => {
+                let nick = value.ok_or("Option `--to` requires a nickname.")?;
+                if nick.is_empty()
+                    || nick.starts_with(CHANNEL_PREFIXES)
+                    || nick.contains([',', ' '])
+                {
+                    return Err(format!(
+                        "Option `--to` only sends to a nickname, not `{nick}`."
+                    ));
+                }
+                self.to = Some(Target::Nick(nick))
+            }
+            "private" => self.to = Some(Target::Private),
+            "stop" => self.stop = true,
+            _ => unreachable!("option_spec only returns known options."),
+        }
+        Ok(())
+    }
+}
+
+fn unknown(opt: &str, comm: PComm, prefix: &str) -> String {
+    let help = match comm {
+        PComm::Search => "moosesearch",
+        PComm::Image => "mooseimg",
+        PComm::Irc => "moose",
+    };
+    format!("Unknown option `{opt}`; see {prefix}{help} --help")
+}
+
+/// Short flags like `-li` or `-p2`, if every flag is one; otherwise it's a name.
+fn short_flags(flags: &str) -> Option<Vec<(&'static str, Option<&str>)>> {
+    let mut opts = vec![];
+    for (i, flag) in flags.char_indices() {
+        let (opt, takes_value) = option_spec(&flags[i..i + flag.len_utf8()])?;
+        if takes_value {
+            let attached = &flags[i + flag.len_utf8()..];
+            opts.push((opt, Some(attached).filter(|a| !a.is_empty())));
+            return Some(opts);
+        }
+        opts.push((opt, None));
+    }
+    (!opts.is_empty()).then_some(opts)
+}
+
+fn parse_rest(comm: PComm, rest: &str, prefix: &str) -> Result<MComm, String> {
+    let mut tokens = Tokens::new(rest);
+    let mut opts = Opts::default();
+    let value = |tokens: &mut Tokens| -> Result<Option<String>, String> {
+        Ok(tokens.next()?.map(|t| t.text.into_owned()))
+    };
+    // options come first; the name is the rest of the line, as it was sent.
+    let name = loop {
+        let name = tokens.rest();
+        let Some(tok) = tokens.next()? else {
+            break String::new();
+        };
+        if tok.quoted {
+            break format!("{}{}", tok.text, tok.after.trim_end_matches(ws));
+        } else if tok.text == "--" {
+            break tokens.rest().to_owned();
+        } else if let Some(long) = tok.text.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((n, v)) => (n, Some(v.to_owned())),
+                None => (long, None),
+            };
+            let Some((opt, takes_value)) = option_spec(name) else {
+                return Err(unknown(&tok.text, comm, prefix));
+            };
+            let value = match inline {
+                Some(_) if !takes_value => {
+                    return Err(format!("Option `--{opt}` does not take a value."));
+                }
+                None if takes_value => value(&mut tokens)?,
+                v => v,
+            };
+            opts.set(opt, value)?;
+        } else if let Some(flags) = tok.text.strip_prefix('-').and_then(short_flags) {
+            for (opt, attached) in flags {
+                let (_, takes_value) = option_spec(opt).expect("flags are known options.");
+                let value = match attached {
+                    Some(v) => Some(v.to_owned()),
+                    None if takes_value => value(&mut tokens)?,
+                    None => None,
+                };
+                opts.set(opt, value)?;
+            }
+        } else {
+            break name.to_owned();
+        }
+    };
+
+    let comm = opts.comm.unwrap_or(comm);
+    if opts.help {
+        return Ok(MComm::Help(comm.topic()));
+    }
+    match comm {
+        PComm::Search => {
+            if opts.selector.is_some() || opts.format.is_some() || opts.to.is_some() || opts.stop {
+                return Err(
+                    "Search does not take --random, --latest, --oldest, --format, --to, --private or --stop."
+                        .to_owned(),
+                );
+            }
+            if opts.next {
+                return Ok(MComm::SearchNext);
+            }
+            if name.is_empty() {
+                return Ok(MComm::Help(HelpTopic::Search));
+            }
+            Ok(MComm::Search(SearchQuery {
+                query: name,
+                page: opts.page.unwrap_or(0),
+                limit: opts.limit,
+            }))
+        }
+        PComm::Image | PComm::Irc => {
+            if opts.next || opts.page.is_some() || opts.limit.is_some() {
+                return Err(format!(
+                    "--page, --limit and --next only work with {prefix}moosesearch"
+                ));
+            }
+            if opts.stop {
+                if !name.is_empty() || opts.selector.is_some() || opts.to.is_some() {
+                    return Err("--stop does not take a moose name or other options.".to_owned());
+                }
+                return Ok(MComm::Stop);
+            }
+            let name = match (opts.selector, name.is_empty()) {
+                (Some(sel), true) => sel.to_owned(),
+                (Some(sel), false) => {
+                    return Err(format!("--{sel} does not take a moose name."));
+                }
+                (None, true) => "random".to_owned(),
+                (None, false) => name,
+            };
+            let format = match comm {
+                PComm::Image => Format::Image,
+                _ => opts.format.unwrap_or(Format::Irc),
+            };
+            Ok(MComm::Moose(MooseArgs {
+                name,
+                format,
+                to: opts.to.unwrap_or_default(),
+            }))
+        }
+    }
+}
+
+/// What a command word, or an alias, does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandKind {
+    Moose,
+    Image,
+    Search,
+    Bots,
+    Help,
+}
+
+impl CommandKind {
+    fn builtin(word: &str) -> Option<Self> {
+        Some(match word {
+            "moose" | "mooseme" => CommandKind::Moose,
+            "mooseimg" => CommandKind::Image,
+            "moosesearch" => CommandKind::Search,
+            "bots" => CommandKind::Bots,
+            "help" => CommandKind::Help,
+            _ => return None,
+        })
+    }
+}
+
+/// How users trigger commands, so we can share channels with other bots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Commands {
+    /// Characters that may prefix a command, e.g. `.moose` or `!moose`.
+    pub prefixes: String,
+    /// Allow `moose foo` without a prefix; never applies to bots and help, or their aliases.
+    pub bare: bool,
+    /// Allow addressing the bot by nick, e.g. `MrMoose: moose foo`.
+    pub addressed: bool,
+    /// Extra command words.
+    pub aliases: HashMap<String, CommandKind>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self {
+            prefixes: ".!".to_owned(),
+            bare: true,
+            addressed: true,
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl Commands {
+    /// Prefix we show in help, e.g. `.`; nothing when commands don't take one.
+    pub fn prefix(&self) -> &str {
+        let end = self.prefixes.chars().next().map_or(0, char::len_utf8);
+        &self.prefixes[..end]
+    }
+
+    /// Strip `nick:` or `nick,` from the start of msg.
+    fn strip_nick<'a>(&self, msg: &'a str, nick: &str) -> Option<&'a str> {
+        if !self.addressed || nick.is_empty() {
+            return None;
+        }
+        let head = msg.get(..nick.len())?;
+        if !head.eq_ignore_ascii_case(nick) {
+            return None;
+        }
+        let rest = msg[nick.len()..].strip_prefix([':', ','])?;
+        rest.starts_with(ws).then(|| rest.trim_start_matches(ws))
+    }
+
+    fn kind(&self, word: &str, bare: bool) -> Option<CommandKind> {
+        let kind = CommandKind::builtin(word).or_else(|| self.aliases.get(word).copied())?;
+        match kind {
+            CommandKind::Bots | CommandKind::Help if bare => None,
+            kind => Some(kind),
+        }
+    }
+}
+
 fn ws(c: char) -> bool {
     c.is_ascii_whitespace()
 }
 
-pub fn parse_moose_args(msg: &str) -> Option<MComm> {
+/// Parse msg into a command, if it is one; nick is our current nickname.
+pub fn parse_moose_args(msg: &str, commands: &Commands, nick: &str) -> Option<MComm> {
+    let (msg, addressed) = match commands.strip_nick(msg, nick) {
+        Some(msg) => (msg, true),
+        None => (msg, false),
+    };
     // we need any whitespace.
-    let (comm, rest) = match msg.split_once(ws) {
+    let (word, rest) = match msg.split_once(ws) {
         Some(cr) => cr,
         None => (msg, ""),
     };
-    let comm = match comm {
-        ".moose" | "!moose" | "moose" | ".mooseme" | "!mooseme" | "mooseme" => PComm::Irc,
-        ".mooseimg" | "!mooseimg" | "mooseimg" => PComm::Image,
-        ".moosesearch" | "!moosesearch" | "moosesearch" => PComm::Search,
-        ".bots" | "!bots" => return Some(MComm::Bots),
-        ".help" | "!help" => return Some(MComm::Help),
-        _ => return None,
+    let (word, prefixed) = match word.chars().next() {
+        Some(c) if commands.prefixes.contains(c) => (&word[c.len_utf8()..], true),
+        _ => (word, false),
     };
-    let rest = rest.trim();
-    let (arg, r) = if rest.is_empty() {
-        ("--random", "")
-    } else {
-        match rest.split_once(ws) {
-            Some((a, r)) => (a, r.trim_start()),
-            None => (rest, ""),
-        }
+    if !prefixed && !addressed && !commands.bare {
+        return None;
+    }
+    let comm = match commands.kind(word, !prefixed && !addressed)? {
+        CommandKind::Moose => PComm::Irc,
+        CommandKind::Image => PComm::Image,
+        CommandKind::Search => PComm::Search,
+        CommandKind::Bots => return Some(MComm::Bots),
+        CommandKind::Help => return Some(MComm::Help(HelpTopic::General)),
+    };
+    let parsed = parse_rest(comm, rest, commands.prefix()).unwrap_or_else(MComm::Invalid);
+    debug!("DEBUG: CMD PARSED {parsed:?}");
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CommandKind, Commands, Format, HelpTopic, MComm, MooseArgs, SearchQuery, Target, Tokens,
+        parse_moose_args,
     };
 
-    match arg {
-        "--" if !r.is_empty() => Some((comm, r).into()),
-        "--" => Some((comm, rest).into()),
-        "-h" | "--help" => Some(MComm::Help),
-        "-s" | "--search" if !r.is_empty() => Some((PComm::Search, r).into()),
-        "-s" | "--search" => Some(MComm::Help),
-        "-i" | "--image" if !r.is_empty() => Some((PComm::Image, r).into()),
-        "-i" | "--image" => Some((PComm::Image, "random").into()),
-        "-r" | "--random" => Some((comm, "random").into()),
-        "-l" | "--latest" => Some((comm, "latest").into()),
-        "-o" | "--oldest" => Some((comm, "oldest").into()),
-        _ => Some((comm, rest).into()),
+    fn parse(msg: &str) -> Option<MComm> {
+        parse_moose_args(msg, &Commands::default(), "MrMoose")
+    }
+
+    fn moose(name: &str, format: Format) -> Option<MComm> {
+        Some(MComm::Moose(MooseArgs {
+            name: name.to_owned(),
+            format,
+            to: Target::Reply,
+        }))
+    }
+
+    fn search(query: &str, page: usize, limit: Option<usize>) -> Option<MComm> {
+        Some(MComm::Search(SearchQuery {
+            query: query.to_owned(),
+            page,
+            limit,
+        }))
+    }
+
+    #[test]
+    fn tokens() {
+        let mut tokens = Tokens::new(r#" a "b c" bob's f\ g "h\"i\\" "#);
+        let mut toks = vec![];
+        while let Some(tok) = tokens.next().unwrap() {
+            toks.push(tok.text.into_owned());
+        }
+        assert_eq!(toks, vec!["a", "b c", "bob's", "f\\", "g", "h\"i\\"]);
+        assert!(Tokens::new("\"open").next().is_err());
+    }
+
+    #[test]
+    fn commands() {
+        use Format::*;
+        let tests = [
+            ("hello", None),
+            (".moose", moose("random", Irc)),
+            ("moose  some  moose ", moose("some  moose", Irc)),
+            (".moose bob's moose", moose("bob's moose", Irc)),
+            ("moose don't do that", moose("don't do that", Irc)),
+            (".moose back\\slash \\o/", moose("back\\slash \\o/", Irc)),
+            (".moose -_- moose", moose("-_- moose", Irc)),
+            (".moose -x", moose("-x", Irc)),
+            (
+                ".moose -l -x",
+                Some(MComm::Invalid(
+                    "--latest does not take a moose name.".to_owned(),
+                )),
+            ),
+            (".moose -i -dash  moose", moose("-dash  moose", Image)),
+            (".moose \"a \\\"b\\\"\" c", moose("a \"b\" c", Irc)),
+            (".moose -l", moose("latest", Irc)),
+            (".moose -li", moose("latest", Image)),
+            (".moose --format=image foo", moose("foo", Image)),
+            (".moose -f image foo", moose("foo", Image)),
+            (".mooseimg", moose("random", Image)),
+            (".moose -- --foo", moose("--foo", Irc)),
+            (".moose \"-weird moose\"", moose("-weird moose", Irc)),
+            (".moose -s foo bar", search("foo bar", 0, None)),
+            (".moosesearch -p2 --limit=3 foo", search("foo", 1, Some(3))),
+            (".moosesearch --page 3 foo", search("foo", 2, None)),
+            (".moosesearch -n", Some(MComm::SearchNext)),
+            (".moosesearch", Some(MComm::Help(HelpTopic::Search))),
+            (".moosesearch --help", Some(MComm::Help(HelpTopic::Search))),
+            (".mooseimg -h", Some(MComm::Help(HelpTopic::Image))),
+            (".moose --help", Some(MComm::Help(HelpTopic::Moose))),
+            (".help", Some(MComm::Help(HelpTopic::General))),
+            (".bots", Some(MComm::Bots)),
+            (".moose --stop", Some(MComm::Stop)),
+            (
+                ".moose --private foo",
+                Some(MComm::Moose(MooseArgs {
+                    name: "foo".to_owned(),
+                    format: Irc,
+                    to: Target::Private,
+                })),
+            ),
+            (
+                ".mooseimg -lt someone",
+                Some(MComm::Moose(MooseArgs {
+                    name: "latest".to_owned(),
+                    format: Image,
+                    to: Target::Nick("someone".to_owned()),
+                })),
+            ),
+            ("bots", None),
+            ("help", None),
+            ("MrMoose: help", Some(MComm::Help(HelpTopic::General))),
+            ("mrmoose, moose foo", moose("foo", Irc)),
+            ("MrMoose: .mooseimg foo", moose("foo", Image)),
+            ("MrMoosey: moose foo", None),
+        ];
+        for (test, expected) in tests {
+            assert_eq!(parse(test), expected, "parsing {test:?}");
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let tests = [
+            ".moose --foo",
+            ".moose --random=yes",
+            ".moose -r foo",
+            ".moose --page 2 foo",
+            ".moosesearch --page two foo",
+            ".moosesearch --limit",
+            ".moose --format=gif foo",
+            ".moose \"unclosed",
+            ".moose --to #channel foo",
+            ".moose --to",
+            ".moosesearch --private foo",
+            ".moose --stop foo",
+        ];
+        for test in tests {
+            assert!(
+                matches!(parse(test), Some(MComm::Invalid(_))),
+                "parsing {test:?}"
+            );
+        }
+        assert_eq!(
+            parse(".moose --foo"),
+            Some(MComm::Invalid(
+                "Unknown option `--foo`; see .moose --help".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn configured() {
+        let commands = Commands {
+            prefixes: "@".to_owned(),
+            bare: false,
+            addressed: false,
+            aliases: [
+                ("elk".to_owned(), CommandKind::Moose),
+                ("mhelp".to_owned(), CommandKind::Help),
+            ]
+            .into(),
+        };
+        let invalid = |s: &str| Some(MComm::Invalid(s.to_owned()));
+        let tests = [
+            (".moose foo", None),
+            ("moose foo", None),
+            ("elk foo", None),
+            ("MrMoose: moose foo", None),
+            ("@moose foo", moose("foo", Format::Irc)),
+            ("@elk -i foo", moose("foo", Format::Image)),
+            ("@mhelp", Some(MComm::Help(HelpTopic::General))),
+            (
+                "@moose --bogus",
+                invalid("Unknown option `--bogus`; see @moose --help"),
+            ),
+        ];
+        for (test, expected) in tests {
+            assert_eq!(
+                parse_moose_args(test, &commands, "MrMoose"),
+                expected,
+                "parsing {test:?}"
+            );
+        }
+
+        // like help itself, its aliases need a prefix.
+        let commands = Commands {
+            aliases: [("mhelp".to_owned(), CommandKind::Help)].into(),
+            ..Commands::default()
+        };
+        assert_eq!(parse_moose_args("mhelp", &commands, "MrMoose"), None);
+        assert_eq!(
+            parse_moose_args("!mhelp", &commands, "MrMoose"),
+            Some(MComm::Help(HelpTopic::General))
+        );
     }
 }
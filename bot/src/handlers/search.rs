@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::{Backend, MooseBackend},
+    filter::{Filter, FilterAction},
+    helpers::{join_lines, privmsg_budget},
+    webreq::ResolveError,
+};
+
+use super::moosecmd::SearchQuery;
+
+/// How long a user can wait before `--next` forgets their last search.
+const CURSOR_TTL: Duration = Duration::from_secs(600);
+/// Most users we remember searches for.
+const MAX_CURSORS: usize = 256;
+
+struct Cursor {
+    query: SearchQuery,
+    /// index into the current page of results.
+    offset: usize,
+    updated: Instant,
+}
+
+/// Where each user left off in their last search, so they can ask for the next page.
+#[derive(Default)]
+pub struct SearchCursors(Mutex<HashMap<String, Cursor>>);
+
+impl SearchCursors {
+    fn take(&self, nick: &str) -> Option<(SearchQuery, usize)> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&nick.to_lowercase())
+            .filter(|c| c.updated.elapsed() < CURSOR_TTL)
+            .map(|c| (c.query, c.offset))
+    }
+
+    fn set(&self, nick: &str, query: SearchQuery, offset: usize) {
+        let mut cursors = self.0.lock().unwrap();
+        cursors.retain(|_, c| c.updated.elapsed() < CURSOR_TTL);
+        if cursors.len() >= MAX_CURSORS {
+            let oldest = cursors
+                .iter()
+                .min_by_key(|(_, c)| c.updated)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                cursors.remove(&oldest);
+            }
+        }
+        cursors.insert(
+            nick.to_lowercase(),
+            Cursor {
+                query,
+                offset,
+                updated: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Run a search (or continue the user's last one when `query` is None)
+/// and return the lines to send to target.
+pub async fn search(
+    backend: &Backend,
+    filter: &Filter,
+    cursors: &SearchCursors,
+    nick: &str,
+    query: Option<SearchQuery>,
+    target: &str,
+    prefix: &str,
+) -> Result<Vec<String>, ResolveError> {
+    let (query, offset) = match query {
+        Some(q) => (q, 0),
+        None => match cursors.take(nick) {
+            Some(cursor) => cursor,
+            None => return Ok(vec!["Error: No search to continue.".to_owned()]),
+        },
+    };
+    let results = backend.search(&query.query, query.page).await?;
+    let page = results
+        .hits
+        .iter()
+        .skip(offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+    if page.is_empty() {
+        let err = if query.page == 0 && offset == 0 {
+            "Error: No results found."
+        } else {
+            "Error: No more results."
+        };
+        return Ok(vec![err.to_owned()]);
+    }
+    let shown = page
+        .iter()
+        .filter_map(|hit| {
+            let name = match filter.action() {
+                FilterAction::Redact => filter.redact(&hit.name),
+                _ if filter.is_match(&hit.name) => return None,
+                _ => hit.name.as_str().into(),
+            };
+            Some(format!("\u{2}{name}\u{2} p.{}", hit.page))
+        })
+        .collect::<Vec<_>>();
+
+    let next_offset = offset + page.len();
+    let next = if next_offset < results.hits.len() {
+        Some((query.clone(), next_offset))
+    } else if query.page + 1 < results.pages {
+        Some((
+            SearchQuery {
+                page: query.page + 1,
+                ..query.clone()
+            },
+            0,
+        ))
+    } else {
+        None
+    };
+    let total = match results.total {
+        Some(1) => ", 1 result".to_owned(),
+        Some(total) => format!(", {total} results"),
+        None => String::new(),
+    };
+    let pages = format!("page {}/{}{total}", query.page + 1, results.pages);
+    let hint = match &next {
+        Some(_) => Some(format!("({pages}; {prefix}moosesearch --next for more)")),
+        None if results.pages > 1 || results.total.is_some_and(|t| t > page.len()) => {
+            Some(format!("({pages})"))
+        }
+        None => None,
+    };
+    if let Some((q, offset)) = next {
+        cursors.set(nick, q, offset);
+    }
+
+    let items = shown
+        .iter()
+        .map(String::as_str)
+        .chain(hint.as_deref())
+        .collect::<Vec<_>>();
+    Ok(join_lines(items, ", ", privmsg_budget(target)))
+}
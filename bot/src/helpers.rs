@@ -1,11 +1,28 @@
 use std::{collections::HashSet, mem};
 
-use irc::proto::{Command, Message};
+use irc::proto::{Command, Message, format::BYTE_LIMIT};
+
+use crate::config::Config;
 
 pub const CONFLICT_FILLER: &str = "_";
+/// Room for the `:nick!user@host ` source the server prepends when relaying our messages.
+const SOURCE_RESERVE: usize = 100;
+/// Least text we put in a PRIVMSG, even when the target leaves no room; the server may truncate it.
+const MIN_BUDGET: usize = 64;
+
+/// Capabilities we request when the server offers them.
+pub const WANTED_CAPS: &[&str] = &[
+    "echo-message",
+    "labeled-response",
+    "batch",
+    "server-time",
+    "message-tags",
+    "account-tag",
+];
 
 pub fn irc_preamble(nick: &str, pass: &str) -> Vec<Message> {
     let mut preamble: Vec<Message> = vec![
+        Command::CAP(None, "LS".to_owned(), Some("302".to_owned()), None).into(),
         Command::NICK(nick.to_owned()).into(),
         Command::USER(nick.to_owned(), env!("CARGO_PKG_NAME").to_owned()).into(),
     ];
@@ -42,29 +59,101 @@ pub fn join_channels(channels: &HashSet<String>) -> impl Iterator<Item = Command
         .map(|s| Command::JOIN(s, None))
 }
 
+/// How many bytes of text can be sent in a single PRIVMSG to target.
+pub fn privmsg_budget(target: &str) -> usize {
+    // CRLF + "PRIVMSG " + target + " :"
+    (BYTE_LIMIT - SOURCE_RESERVE - 2 - 8 - 2)
+        .saturating_sub(target.len())
+        .max(MIN_BUDGET)
+}
+
+/// Split s into pieces of at most max bytes, on char boundaries.
+/// A char longer than max is a piece of its own.
+fn split_at_most(mut s: &str, max: usize) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        let first = s.chars().next()?;
+        let mut end = max.min(s.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, rest) = s.split_at(end.max(first.len_utf8()));
+        s = rest;
+        Some(piece)
+    })
+}
+
+/// Join items with sep, starting a new line whenever a line would exceed max bytes.
+/// Items longer than max are split over lines of their own.
+pub fn join_lines<I>(items: I, sep: &str, max: usize) -> Vec<String>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut ret = vec![];
+    let mut cur = String::default();
+    items.into_iter().for_each(|item| {
+        let item = item.as_ref();
+        if !cur.is_empty() && cur.len() + sep.len() + item.len() > max {
+            ret.push(mem::take(&mut cur));
+        }
+        if item.len() > max {
+            let mut pieces = split_at_most(item, max)
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>();
+            cur = pieces.pop().unwrap_or_default();
+            ret.extend(pieces);
+            return;
+        }
+        if !cur.is_empty() {
+            cur.push_str(sep);
+        }
+        cur.push_str(item);
+    });
+    if !cur.is_empty() {
+        ret.push(cur);
+    }
+    ret
+}
+
 // pub fn part_channels(channels: &HashSet<String>) -> impl Iterator<Item = Command> {
 //     join_part_channels(channels)
 //         .into_iter()
 //         .map(|s| Command::PART(s, None))
 // }
 
-fn security<'a>(tls: bool) -> irc::connection::Security<'a> {
+pub fn security<'a>(tls: bool, starttls: bool) -> irc::connection::Security<'a> {
     if tls {
         irc::connection::Security::Secured {
             root_cert_path: None,
             client_cert_path: None,
             client_key_path: None,
         }
+    } else if starttls {
+        irc::connection::Security::StartTls {
+            root_cert_path: None,
+            client_cert_path: None,
+            client_key_path: None,
+        }
     } else {
         irc::connection::Security::Unsecured
     }
 }
 
-pub fn client_config(server: &'_ str, port: u16, tls: bool) -> irc::connection::Config<'_> {
+/// STARTTLS only applies when tls is false.
+pub fn client_config<'a>(
+    config: &Config,
+    server: &'a str,
+    port: u16,
+    tls: bool,
+    proxy: Option<irc::connection::Proxy<'a>>,
+) -> irc::connection::Config<'a> {
     irc::connection::Config {
         server,
         port,
-        security: security(tls),
+        security: security(tls, config.starttls),
+        proxy,
+        family: config.prefer_family,
+        bind: config.bind,
     }
 }
 
@@ -113,3 +202,49 @@ macro_rules! debug {
         }
     };
 }
+
+/// A directory for tests, removed when dropped so failing tests clean up too.
+#[cfg(test)]
+pub struct TempDir(pub std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{prefix}-{:x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{join_lines, privmsg_budget};
+
+    #[test]
+    fn join_lines_fits() {
+        assert_eq!(
+            join_lines(["aaa", "bbb", "cc", "dddddddd", "e"], ", ", 8),
+            vec!["aaa, bbb", "cc", "dddddddd", "e"]
+        );
+        assert_eq!(
+            join_lines(["a", "0123456789", "b"], ", ", 4),
+            vec!["a", "0123", "4567", "89", "b"]
+        );
+        // never in the middle of a char.
+        assert_eq!(join_lines(["mööse"], ", ", 2), vec!["m", "ö", "ö", "se"]);
+        assert!(join_lines(Vec::<String>::new(), ", ", 8).is_empty());
+    }
+
+    #[test]
+    fn budget() {
+        assert_eq!(privmsg_budget("#moose"), 512 - 100 - 12 - 6);
+        assert_eq!(privmsg_budget(&"#".repeat(600)), 64);
+    }
+}
@@ -18,19 +18,22 @@ use std::{collections::HashSet, path::PathBuf};
 
 use config::parse_args;
 use futures::StreamExt;
-use helpers::client_config;
 use tasks::{
     invite::invite_task,
     receiver::receiver_task,
-    sender::{create_send_recv_pair, sender_task},
+    sender::{Budget, create_send_recv_pair, sender_task},
     shutdown::shutdown_task,
 };
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+mod backend;
 mod config;
+mod duration;
+mod filter;
 mod handlers;
 mod helpers;
+mod sts;
 mod tasks;
 mod webreq;
 
@@ -56,29 +59,55 @@ fn main() {
         let stop_token = CancellationToken::new();
         let shutdown = shutdown_task(stop_token.clone(), sendi.clone());
 
-        let (server, port) = config
-            .host
-            .split_once(':')
-            .map(|(s, p)| (s, p.parse::<u16>().unwrap_or(default_port(config.tls))))
-            .unwrap_or_else(|| (&config.host, default_port(config.tls)));
-        let (sendm, recvm) = irc::connection::Connection::new(
-            client_config(server, port, config.tls),
-            irc::Codec {},
-        )
-        .await
-        .expect("Expected to set up connection.")
-        .split();
+        // a line that's too long shouldn't drop the connection.
+        let codec = irc::Codec::builder().recover(true).build();
+        let discarded = codec.discarded();
+        let mut connection = None;
+        for host in std::iter::once(&config.host).chain(&config.fallback_hosts) {
+            let connected = if let Some(path) = host.strip_prefix("unix:") {
+                irc::Connection::unix(path, codec.clone())
+                    .await
+                    .map(|c| (c, None))
+            } else {
+                let (server, port) = host
+                    .split_once(':')
+                    .map(|(s, p)| (s, p.parse::<u16>().unwrap_or(default_port(config.tls))))
+                    .unwrap_or_else(|| (host, default_port(config.tls)));
+                sts::connect(&config, server, port, codec.clone()).await
+            };
+            match connected {
+                Ok(c) => {
+                    connection = Some(c);
+                    break;
+                }
+                Err(e) => eprintln!("WARN: [main] Failed to connect to {host}: {e}"),
+            }
+        }
+        let (connection, sts) = connection.expect("Expected to set up connection.");
+        let (sendm, recvm) = connection.split();
         let (sendo, recvo) = create_send_recv_pair();
+        let shutdown_timeout = config.shutdown_timeout;
         let sender = sender_task(
-            config.send_burst,
-            config.send_delay,
+            Budget::new(config.send_burst, config.send_delay),
+            Budget::new(config.bulk_burst, config.bulk_delay),
             sendm,
             recvo,
             stop_token.clone(),
+            config.quit_message.clone(),
+            shutdown_timeout,
         );
 
-        let receiver = receiver_task(config, recvm, sendo, sendi, stop_token);
-        let _ = tokio::join!(sender, receiver, shutdown);
+        let receiver = receiver_task(config, recvm, discarded, sts, sendo, sendi, stop_token);
+        let (sendm, recvm, _) = tokio::join!(sender, receiver, shutdown);
+        if let (Ok(sendm), Ok(recvm)) = (sendm, recvm)
+            && let Ok(connection) = sendm.reunite(recvm)
+        {
+            match tokio::time::timeout(shutdown_timeout, connection.shutdown()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => eprintln!("WARN: [main] Failed to close connection: {e}"),
+                Err(_) => eprintln!("WARN: [main] Timed out closing connection."),
+            }
+        }
         let _ = inviter.join();
     });
 }
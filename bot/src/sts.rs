@@ -0,0 +1,329 @@
+/* Copyright (C) 2025  Anthony DeDominic
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{SinkExt, StreamExt};
+use irc::{
+    Codec,
+    connection::{self, Connection, Proxy},
+    proto::{Command, Message, command::Numeric, sts::Policy},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, from_dur_str, open_path_and_deserialize, save_json, to_dur_str},
+    helpers::{client_config, security},
+};
+
+/// How long to wait for the server to list its capabilities before registering.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A policy a server gave us over TLS, which we honor until it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Stored {
+    pub port: u16,
+    /// port speaks plaintext until STARTTLS.
+    #[serde(default)]
+    pub starttls: bool,
+    #[serde(deserialize_with = "from_dur_str", serialize_with = "to_dur_str")]
+    pub duration: Duration,
+    /// seconds since the unix epoch.
+    pub expires: u64,
+}
+
+/// Policies by lowercase host.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Policies(HashMap<String, Stored>);
+
+/// Policies are kept next to the invite file.
+pub fn path(invite_file: &Path) -> PathBuf {
+    invite_file.with_file_name("sts.json")
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl Policies {
+    pub fn load(path: &Path) -> Self {
+        open_path_and_deserialize(path).unwrap_or_else(|e: io::Error| {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("WARN: [sts] Ignoring policies in {path:?}: {e}");
+            }
+            Policies::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_json(path, self)
+    }
+
+    /// Policy we have to follow for host, if it hasn't expired.
+    pub fn get(&self, host: &str, now: SystemTime) -> Option<&Stored> {
+        self.0
+            .get(&host.to_lowercase())
+            .filter(|p| p.expires > unix(now))
+    }
+
+    /// Remember or refresh the policy for host; a duration of zero removes it.
+    pub fn update(
+        &mut self,
+        host: &str,
+        port: u16,
+        starttls: bool,
+        duration: Duration,
+        now: SystemTime,
+    ) {
+        let host = host.to_lowercase();
+        if duration.is_zero() {
+            self.0.remove(&host);
+        } else {
+            let expires = unix(now).saturating_add(duration.as_secs());
+            self.0.insert(
+                host,
+                Stored {
+                    port,
+                    starttls,
+                    duration,
+                    expires,
+                },
+            );
+        }
+        let now = unix(now);
+        self.0.retain(|_, p| p.expires > now);
+    }
+}
+
+/// Ask a plaintext server for its capabilities before registering, for the port to upgrade to.
+///
+/// The replies are ours to consume; registration asks again.
+pub async fn probe(connection: &mut Connection<Codec>) -> Option<Policy> {
+    let ls = Command::CAP(None, "LS".to_owned(), Some("302".to_owned()), None);
+    connection.send(ls.into()).await.ok()?;
+
+    let mut caps = String::new();
+    let listen = async {
+        while let Some(message) = connection.next().await {
+            let Ok(Ok(message)) = message else {
+                continue;
+            };
+            match message.command {
+                Command::CAP(_, sub, a, b) if sub == "LS" => {
+                    // `CAP * LS * :caps` means more caps follow.
+                    let (more, list) = match (a, b) {
+                        (Some(more), Some(list)) if more == "*" => (true, list),
+                        (Some(list), _) => (false, list),
+                        _ => (false, String::new()),
+                    };
+                    caps.push(' ');
+                    caps.push_str(&list);
+                    if !more {
+                        return;
+                    }
+                }
+                Command::PING(token) => {
+                    let _ = connection.send(Command::PONG(token, None).into()).await;
+                }
+                // servers without capabilities won't answer.
+                Command::Numeric(Numeric::ERR_UNKNOWNCOMMAND | Numeric::ERR_NOTREGISTERED, _)
+                | Command::ERROR(_) => return,
+                _ => (),
+            }
+        }
+    };
+    if tokio::time::timeout(PROBE_TIMEOUT, listen).await.is_err() {
+        eprintln!("WARN: [sts] Timed out waiting for capabilities.");
+    }
+    Policy::offered(&caps)
+}
+
+/// Where to keep a policy the server offers during registration on a secure connection.
+#[derive(Debug, Clone)]
+pub struct Remember {
+    host: String,
+    port: u16,
+    starttls: bool,
+    store: PathBuf,
+}
+
+impl Remember {
+    /// Save or refresh the policy if message is a `CAP LS` or `CAP NEW` that offers one.
+    pub fn offered(&self, message: &Message) {
+        let Command::CAP(_, sub, a, b) = &message.command else {
+            return;
+        };
+        if sub != "LS" && sub != "NEW" {
+            return;
+        }
+        // the list is last; `CAP * LS * :caps` has a `*` before it.
+        let Some(duration) = [b, a]
+            .into_iter()
+            .flatten()
+            .find_map(|caps| Policy::offered(caps))
+            .and_then(|p| p.duration)
+        else {
+            return;
+        };
+        let mut policies = Policies::load(&self.store);
+        policies.update(
+            &self.host,
+            self.port,
+            self.starttls,
+            duration,
+            SystemTime::now(),
+        );
+        if let Err(e) = policies.save(&self.store) {
+            eprintln!(
+                "WARN: [sts] Failed to save policies to {:?}: {e}",
+                self.store
+            );
+        }
+    }
+}
+
+/// Connect to server, upgrading to TLS when it has a policy.
+///
+/// A host with a stored policy is never reached in plaintext; if TLS fails, so do we.
+/// Only plaintext connections are probed; secure ones offer their policy during registration,
+/// which is where to pass it to the returned [`Remember`].
+pub async fn connect(
+    config: &Config,
+    server: &str,
+    port: u16,
+    codec: Codec,
+) -> Result<(Connection<Codec>, Option<Remember>), connection::Error> {
+    let proxy = config.proxy.as_deref().map(Proxy::parse).transpose()?;
+    let store = config.invite_file.as_deref().map(path);
+    let policies = store.as_deref().map(Policies::load).unwrap_or_default();
+
+    let (mut port, mut tls, mut starttls) = (port, config.tls, config.starttls);
+    if !tls && let Some(stored) = policies.get(server, SystemTime::now()) {
+        eprintln!(
+            "INFO: [sts] {server} requires TLS; connecting to port {}.",
+            stored.port
+        );
+        (port, tls, starttls) = (stored.port, !stored.starttls, stored.starttls);
+    }
+    let client = |port, tls, starttls| {
+        let mut client = client_config(config, server, port, tls, proxy);
+        client.security = security(tls, starttls);
+        client
+    };
+    let mut connection = Connection::new(client(port, tls, starttls), codec.clone()).await?;
+
+    if !connection.is_secure()
+        && let Some(sts_port) = probe(&mut connection).await.and_then(|p| p.port)
+    {
+        eprintln!("INFO: [sts] {server} offers TLS on port {sts_port}; reconnecting.");
+        let _ = connection.shutdown().await;
+        (port, tls, starttls) = (sts_port, true, false);
+        connection = Connection::new(client(port, tls, starttls), codec).await?;
+    }
+
+    // a policy only counts when it comes over TLS, for the port we would reuse.
+    let remember = store
+        .filter(|_| connection.is_secure())
+        .map(|store| Remember {
+            host: server.to_owned(),
+            port,
+            starttls: !tls,
+            store,
+        });
+    Ok((connection, remember))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use irc::{Codec, connection::Connection, proto::sts::Policy};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, duplex};
+
+    use irc::proto::parse;
+
+    use super::{Policies, Remember, probe};
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn policies() {
+        let now = SystemTime::now();
+        let mut policies = Policies::default();
+        policies.update("IRC.example.com", 6697, false, Duration::from_secs(60), now);
+        let port = |p: &Policies, host, now| p.get(host, now).map(|s| s.port);
+        assert_eq!(port(&policies, "irc.example.COM", now), Some(6697));
+        assert_eq!(port(&policies, "other.example.com", now), None);
+        assert_eq!(
+            port(&policies, "irc.example.com", now + Duration::from_secs(61)),
+            None
+        );
+
+        let tmp = TempDir::new("moose-sts");
+        let dir = &tmp.0;
+        let path = super::path(&dir.join("invites.json"));
+        policies.save(&path).unwrap();
+        let loaded = Policies::load(&path);
+        assert_eq!(port(&loaded, "irc.example.com", now), Some(6697));
+
+        policies.update("irc.example.com", 6697, false, Duration::ZERO, now);
+        assert_eq!(port(&policies, "irc.example.com", now), None);
+        assert_eq!(Policies::load(&dir.join("missing.json")).0.len(), 0);
+
+        // what the server offers while we register over STARTTLS.
+        let remember = Remember {
+            host: "irc.example.com".to_owned(),
+            port: 6667,
+            starttls: true,
+            store: path.clone(),
+        };
+        let ls = |l: &str| parse::message(&format!("{l}\r\n")).unwrap();
+        remember.offered(&ls(":irc.host CAP * LS * :batch sasl"));
+        remember.offered(&ls(":irc.host CAP * LS :sts=duration=300 server-time"));
+        let stored = *Policies::load(&path).get("irc.example.com", now).unwrap();
+        assert_eq!((stored.port, stored.starttls), (6667, true));
+        assert_eq!(stored.duration, Duration::from_secs(300));
+    }
+
+    #[tokio::test]
+    async fn probing() {
+        let (client, server) = duplex(1024);
+        let mut server = BufReader::new(server);
+        server
+            .write_all(b":irc.host CAP * LS * :batch sasl\r\n:irc.host CAP * LS :sts=port=6697,duration=300 server-time\r\n")
+            .await
+            .unwrap();
+
+        let mut connection = Connection::from_stream(client, Codec::new());
+        assert_eq!(
+            probe(&mut connection).await,
+            Some(Policy {
+                port: Some(6697),
+                duration: Some(Duration::from_secs(300)),
+                preload: false,
+            })
+        );
+        let mut line = String::new();
+        server.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "CAP LS 302\r\n");
+    }
+}
@@ -22,6 +22,8 @@ pub fn invite_task(
     // tokio spawn_blocking is not intended for long (infinite) lived tasks.
     thread::spawn(move || {
         if let Some((mut invites, ifile)) = invites {
+            // changes we failed to save.
+            let mut dirty = false;
             while let Some(invite) = recv.blocking_recv() {
                 debug!("DEBUG: m{invite:?} - invited:{invites:?}");
                 let changed = match invite {
@@ -30,10 +32,23 @@ pub fn invite_task(
                     InviteMsg::Quit => break,
                 };
                 debug!("DEBUG: changed:{changed} - invited:{invites:?}");
-                if changed && let Err(e) = save_invite(&ifile, &invites) {
-                    eprintln!("WARN: Failed to save invite changes: {e}");
+                if changed || dirty {
+                    dirty = save_invite(&ifile, &invites)
+                        .inspect_err(|e| eprintln!("WARN: Failed to save invite changes: {e}"))
+                        .is_err();
                 }
             }
+            // flush whatever is left, e.g. invites sent while shutting down.
+            while let Ok(invite) = recv.try_recv() {
+                dirty |= match invite {
+                    InviteMsg::Joined(chan) => invites.insert(chan),
+                    InviteMsg::Kicked(chan) => invites.remove(&chan),
+                    InviteMsg::Quit => false,
+                };
+            }
+            if dirty && let Err(e) = save_invite(&ifile, &invites) {
+                eprintln!("WARN: Failed to save invite changes: {e}");
+            }
         }
         eprintln!("INFO: [tasks/invite] Shutting down.");
     })
@@ -1,7 +1,18 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use futures::{StreamExt, stream::SplitStream};
-use irc::{Codec, Connection, proto::Command};
+use irc::{
+    Codec, Connection,
+    codec::Discarded,
+    proto::{
+        Command, Message,
+        batch::{Assembler, Batch, Event, Item, Kind},
+        tags,
+    },
+};
 use tokio::{
     sync::{RwLock, Semaphore, mpsc::Sender},
     task::JoinHandle,
@@ -10,47 +21,137 @@ use tokio::{
 use tokio_util::sync::CancellationToken;
 
 use crate::{
+    backend::{Backend, HttpBackend, LocalBackend},
     capture_clone,
     config::Config,
+    debug,
     handlers::{handler, ircstate::IrcState},
     helpers::irc_preamble,
+    sts::Remember,
+    webreq::cache::MooseCache,
 };
 
 use super::{invite::InviteMsg, sender};
 
+/// Messages of a complete batch, and whether to handle them.
+///
+/// History playback is only logged; replayed commands were already answered.
+/// The QUITs and JOINs of netsplits only change who is in our channels.
+fn unbatch(batch: Batch) -> Vec<(Message, bool)> {
+    let handle = match batch.kind() {
+        Kind::ChatHistory { target } => {
+            debug!("DEBUG: [irc] Ignoring history of {target}");
+            return vec![];
+        }
+        Kind::Netsplit { server1, server2 } => {
+            eprintln!("INFO: [irc] Netsplit between {server1} and {server2}");
+            false
+        }
+        Kind::Netjoin { server1, server2 } => {
+            eprintln!("INFO: [irc] Netjoin between {server1} and {server2}");
+            false
+        }
+        Kind::LabeledResponse | Kind::Other(_) => true,
+    };
+    // responses in a batch are labeled by the batch.
+    let label = batch.label().map(str::to_owned);
+    batch
+        .items
+        .into_iter()
+        .flat_map(|item| match item {
+            Item::Message(msg) => vec![(msg, handle)],
+            Item::Batch(batch) => unbatch(batch)
+                .into_iter()
+                .map(|(msg, h)| (msg, h && handle))
+                .collect(),
+        })
+        .map(|(msg, handle)| match &label {
+            Some(label) if msg.label().is_none() => (msg.with_tag(tags::LABEL, label), handle),
+            _ => (msg, handle),
+        })
+        .collect()
+}
+
+/// Age of messages, measured against the newest server-time we have seen rather than our clock.
+struct ServerClock {
+    newest: Option<SystemTime>,
+    /// messages older than this were likely replayed; zero disables.
+    stale: Duration,
+}
+
+impl ServerClock {
+    fn new(stale: Duration) -> Self {
+        Self {
+            newest: None,
+            stale,
+        }
+    }
+
+    fn is_stale(&mut self, msg: &Message) -> bool {
+        let Some(time) = msg.time() else {
+            return false;
+        };
+        let newest = *self
+            .newest
+            .insert(self.newest.map_or(time, |n| n.max(time)));
+        !self.stale.is_zero()
+            && newest
+                .duration_since(time)
+                .is_ok_and(|age| age > self.stale)
+    }
+}
+
 pub fn receiver_task(
     config: Config,
     mut recv: SplitStream<Connection<Codec>>,
+    discarded: Discarded,
+    sts: Option<Remember>,
     sendo: sender::Sender,
     sendi: Sender<InviteMsg>,
     stop_token: CancellationToken,
-) -> JoinHandle<()> {
+) -> JoinHandle<SplitStream<Connection<Codec>>> {
     tokio::task::spawn(async move {
         let _dropg = stop_token.drop_guard_ref();
         let pass = config.pass.clone().unwrap_or_default();
         let pream = irc_preamble(config.nick.as_str(), pass.as_str());
         pream.into_iter().for_each(|m| sendo.lossy_send(m));
 
-        let irc_state = Arc::new(RwLock::new(IrcState::new(
+        let backend = match config.moose_dir {
+            Some(dir) => Backend::Local(LocalBackend::new(dir, config.moose_url.clone())),
+            None => Backend::Http(Box::new(HttpBackend::new(
+                config.moose_url.clone(),
+                MooseCache::new(config.cache_ttl, config.cache_size, config.cache_dir),
+            ))),
+        };
+        let mut irc_state = IrcState::new(
             config.nick,
             config.nickserv,
             config.channels,
             config.moose_url,
             config.moose_delay,
-        )));
+            backend,
+            config.filter,
+            config.commands,
+        );
+        irc_state.trusted_accounts = config.trusted_accounts;
+        let irc_state = Arc::new(RwLock::new(irc_state));
         let task_limit = Arc::new(Semaphore::new(64));
+        let mut batches = Assembler::new();
+        let mut clock = ServerClock::new(config.stale_command);
         let mut double_timeout = false;
+        let mut seen_discarded = 0;
+        let mut seen_dropped = 0;
         'l: while let Some(msg) = tokio::select! {
                 m = recv.next() => m,
                 _ = stop_token.cancelled() => None,
                 _ = time::sleep(Duration::from_secs(60)) => {
                     if double_timeout {
-                        eprintln!(" ERR: [task/receiver] TCP Connection is likely half open or the IRC server is broken.");
+                        eprintln!(" ERR: [task/receiver] TCP Connection is likely half open or the IRC server is broken. Queued: {}", sendo.queued());
                         None
                     } else {
                         #[cfg(debug_assertions)]
                         {
-                            eprintln!("DEBG: [task/receiver] Have not heard from server in 60 seconds; Sending PING.");
+                            eprintln!("DEBG: [task/receiver] Have not heard from server in 60 seconds; Sending PING. Queued: {}", sendo.queued());
                         }
                         double_timeout = true;
                         // See if we're still connected.
@@ -61,19 +162,55 @@ pub fn receiver_task(
                 },
         } {
             double_timeout = false;
+            let count = discarded.get();
+            if count > seen_discarded {
+                eprintln!(
+                    "WARN: [task/receiver] Discarded {} line(s) that were too long.",
+                    count - seen_discarded
+                );
+                seen_discarded = count;
+            }
+            let dropped = batches.dropped();
+            if dropped > seen_dropped {
+                eprintln!(
+                    "WARN: [task/receiver] Dropped {} batch(es) that were too large or never ended.",
+                    dropped - seen_dropped
+                );
+                seen_dropped = dropped;
+            }
             match msg {
                 Ok(Ok(msg)) => {
-                    tokio::spawn(capture_clone! {
-                        (irc_state, sendo, sendi, task_limit)
-                        async move {
-                            if let Ok(s) = task_limit.try_acquire() {
-                                handler::handle(irc_state, msg, config.disable_search, sendo, sendi).await;
-                                drop(s)
-                            } else {
-                                eprintln!("WARN: [irc] Too many tasks; dropping messages.");
-                            }
+                    if let Some(sts) = &sts {
+                        sts.offered(&msg);
+                    }
+                    let msgs = match batches.push(msg) {
+                        None => vec![],
+                        Some(Event::Message(msg)) => vec![(msg, true)],
+                        Some(Event::Batch(batch)) => unbatch(batch),
+                    };
+                    for (msg, handle) in msgs {
+                        // negotiation spans several messages, so handle them in order.
+                        if let Command::CAP(..) = msg.command {
+                            handler::negotiate(&irc_state, msg.command, &sendo).await;
+                            continue;
+                        }
+                        handler::track(&irc_state, &msg).await;
+                        if !handle {
+                            continue;
                         }
-                    });
+                        let stale = clock.is_stale(&msg);
+                        tokio::spawn(capture_clone! {
+                            (irc_state, sendo, sendi, task_limit)
+                            async move {
+                                if let Ok(s) = task_limit.try_acquire() {
+                                    handler::handle(irc_state, msg, config.disable_search, stale, sendo, sendi).await;
+                                    drop(s)
+                                } else {
+                                    eprintln!("WARN: [irc] Too many tasks; dropping messages.");
+                                }
+                            }
+                        });
+                    }
                 }
                 Ok(Err(e)) => match e {
                     irc::proto::parse::Error::Parse { input, nom } => {
@@ -86,6 +223,80 @@ pub fn receiver_task(
                 }
             }
         }
-        eprintln!("INFO: [task/receiver] Shutting down.")
+        eprintln!("INFO: [task/receiver] Shutting down.");
+        recv
     })
 }
+
+#[cfg(test)]
+mod test {
+    use irc::proto::{
+        Command,
+        batch::{Assembler, Event},
+        parse,
+    };
+
+    use std::time::Duration;
+
+    use super::{ServerClock, unbatch};
+
+    #[test]
+    fn batches() {
+        let mut batches = Assembler::new();
+        let lines = [
+            "@label=m1 :irc.host BATCH +outer labeled-response",
+            "@batch=outer :irc.host BATCH +inner chathistory #c",
+            "@batch=inner :nick!u@h PRIVMSG #c :.moose",
+            ":irc.host BATCH -inner",
+            "@batch=outer :irc.host 404 MrMoose #c :Cannot send to channel",
+            ":irc.host BATCH -outer",
+        ];
+        let events = lines
+            .into_iter()
+            .filter_map(|l| batches.push(parse::message(&format!("{l}\r\n")).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        let Some(Event::Batch(batch)) = events.into_iter().next() else {
+            panic!("expected a batch");
+        };
+        let msgs = unbatch(batch);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].0.label(), Some("m1"));
+        assert!(msgs[0].1);
+
+        let lines = [
+            ":irc.host BATCH +split netsplit irc.a irc.b",
+            "@batch=split :gone!u@h QUIT :irc.a irc.b",
+            ":irc.host BATCH -split",
+        ];
+        let batch = lines
+            .into_iter()
+            .find_map(|l| batches.push(parse::message(&format!("{l}\r\n")).unwrap()));
+        let Some(Event::Batch(batch)) = batch else {
+            panic!("expected a batch");
+        };
+        // followed for membership, but not handled.
+        let msgs = unbatch(batch);
+        assert_eq!(msgs.len(), 1);
+        assert!(matches!(msgs[0].0.command, Command::QUIT(_)));
+        assert!(!msgs[0].1);
+    }
+
+    #[test]
+    fn stale() {
+        let msg = |time: &str| {
+            parse::message(&format!("@time={time} :n!u@h PRIVMSG #c :.moose\r\n")).unwrap()
+        };
+        let mut clock = ServerClock::new(Duration::from_secs(60));
+        // our own clock doesn't matter, only what the server has sent.
+        assert!(!clock.is_stale(&msg("2001-01-01T00:00:00.000Z")));
+        assert!(!clock.is_stale(&msg("2001-01-01T00:05:00.000Z")));
+        assert!(clock.is_stale(&msg("2001-01-01T00:03:59.000Z")));
+        assert!(!clock.is_stale(&msg("2001-01-01T00:04:30.000Z")));
+        assert!(!clock.is_stale(&parse::message(":n!u@h PRIVMSG #c :.moose\r\n").unwrap()));
+
+        let mut clock = ServerClock::new(Duration::ZERO);
+        assert!(!clock.is_stale(&msg("2001-01-01T00:05:00.000Z")));
+        assert!(!clock.is_stale(&msg("2001-01-01T00:00:00.000Z")));
+    }
+}
@@ -1,83 +1,639 @@
-use std::{num::NonZero, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZero,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use futures::{SinkExt, stream::SplitSink};
-use governor::{Quota, RateLimiter};
-use irc::{Codec, Connection, proto::Message};
+use irc::{
+    Codec, Connection,
+    proto::{Command, Message, Tag},
+};
 use tokio::{
-    sync::mpsc::{self},
+    sync::{
+        Notify,
+        mpsc::{self},
+    },
     task::JoinHandle,
+    time,
 };
 use tokio_util::sync::CancellationToken;
 
+/// Tallest moose we are willing to send.
+pub const MAX_MOOSE_LINES: usize = 64;
+/// Most moose that can wait to be sent to one target.
+const MAX_QUEUED_MOOSE: usize = 3;
+/// How long we wait for the server to echo, or reject, a labeled line.
+const LABEL_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum MooseError {
+    #[error("That moose is too tall to send here ({0} lines; at most {MAX_MOOSE_LINES}).")]
+    TooTall(usize),
+    #[error("Too many moose are waiting to be sent here; try again later.")]
+    Full,
+}
+
+struct Moose {
+    /// nick that asked for the moose.
+    requester: String,
+    lines: VecDeque<Message>,
+}
+
+/// One line of a moose, ready to send.
+struct Line {
+    msg: Message,
+    /// lowercase target.
+    target: String,
+    requester: String,
+}
+
+#[derive(Default)]
+struct Queues {
+    /// targets with moose waiting, in the order they get to send their next line.
+    order: VecDeque<String>,
+    /// moose waiting per target; the front moose is being sent.
+    moose: HashMap<String, VecDeque<Moose>>,
+}
+
+/// Moose waiting to be sent.
+///
+/// Each target gets its moose one whole moose at a time,
+/// while targets take turns sending a line so one channel can't starve the rest.
+#[derive(Default)]
+struct MooseQueue {
+    queues: Mutex<Queues>,
+    ready: Notify,
+}
+
+impl MooseQueue {
+    fn push(&self, target: &str, requester: &str, lines: Vec<Message>) -> Result<(), MooseError> {
+        if lines.len() > MAX_MOOSE_LINES {
+            return Err(MooseError::TooTall(lines.len()));
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let key = target.to_lowercase();
+        let mut queues = self.queues.lock().unwrap();
+        let waiting = queues.moose.entry(key.clone()).or_default();
+        if waiting.len() >= MAX_QUEUED_MOOSE {
+            return Err(MooseError::Full);
+        }
+        waiting.push_back(Moose {
+            requester: requester.to_owned(),
+            lines: lines.into(),
+        });
+        if waiting.len() == 1 {
+            queues.order.push_back(key);
+        }
+        drop(queues);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Drop every moose waiting for target, including one being sent.
+    fn stop(&self, target: &str) -> bool {
+        let key = target.to_lowercase();
+        let mut queues = self.queues.lock().unwrap();
+        queues.order.retain(|t| *t != key);
+        queues.moose.remove(&key).is_some()
+    }
+
+    fn lines(&self) -> usize {
+        let queues = self.queues.lock().unwrap();
+        queues.moose.values().flatten().map(|m| m.lines.len()).sum()
+    }
+
+    fn pop(&self) -> Option<Line> {
+        let mut queues = self.queues.lock().unwrap();
+        let target = queues.order.pop_front()?;
+        let waiting = queues.moose.get_mut(&target)?;
+        let current = waiting.front_mut()?;
+        let msg = current.lines.pop_front()?;
+        let requester = if current.lines.is_empty() {
+            waiting.pop_front()?.requester
+        } else {
+            current.requester.clone()
+        };
+        if waiting.is_empty() {
+            queues.moose.remove(&target);
+        } else {
+            queues.order.push_back(target.clone());
+        }
+        Some(Line {
+            msg,
+            target,
+            requester,
+        })
+    }
+}
+
+/// A labeled line we are waiting to hear back about.
+struct Sent {
+    /// lowercase target.
+    target: String,
+    /// who asked for the moose; replies have none.
+    requester: Option<String>,
+    at: Instant,
+}
+
+/// Labels moose lines and replies, when the server supports `labeled-response`,
+/// so echoes and errors can be traced back to the line, and who asked for the moose.
+#[derive(Default)]
+struct Labels {
+    enabled: AtomicBool,
+    next: AtomicU64,
+    sent: Mutex<HashMap<String, Sent>>,
+}
+
+impl Labels {
+    fn tag(&self, line: Line) -> Message {
+        self.label(line.msg, line.target, Some(line.requester))
+    }
+
+    /// Label a reply to a user, which is only ever a PRIVMSG or NOTICE.
+    fn reply(&self, msg: Message) -> Message {
+        let target = match &msg.command {
+            Command::PRIVMSG(target, _) | Command::NOTICE(target, _) => target.to_lowercase(),
+            _ => return msg,
+        };
+        self.label(msg, target, None)
+    }
+
+    fn label(&self, mut msg: Message, target: String, requester: Option<String>) -> Message {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return msg;
+        }
+        let label = format!("m{:x}", self.next.fetch_add(1, Ordering::Relaxed));
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, s| s.at.elapsed() < LABEL_TTL);
+        sent.insert(
+            label.clone(),
+            Sent {
+                target,
+                requester,
+                at: Instant::now(),
+            },
+        );
+        msg.tags.push(Tag {
+            key: "label".to_owned(),
+            value: Some(label),
+        });
+        msg
+    }
+}
+
+/// How urgently a message needs to reach the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Keeps us connected (PONG) or changes our registration (NICK, QUIT);
+    /// never waits for a rate budget.
+    Critical,
+    /// Replies to users and everything else.
+    Interactive,
+    /// Moose art; only sent when nothing else is waiting.
+    Bulk,
+}
+
+impl Priority {
+    fn of(msg: &Message) -> Self {
+        match msg.command {
+            Command::PING(..)
+            | Command::PONG(..)
+            | Command::NICK(..)
+            | Command::QUIT(..)
+            | Command::PASS(..)
+            | Command::USER(..)
+            | Command::CAP(..)
+            | Command::AUTHENTICATE(..) => Priority::Critical,
+            _ => Priority::Interactive,
+        }
+    }
+}
+
+/// Flood control modeled after RFC1459 section 8.10.
+///
+/// Every message pushes a timer `penalty` further into the future;
+/// we may send as long as the timer stays within `burst` penalties of now.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    penalty: Duration,
+    /// how far ahead of now the timer may be before sending another message.
+    slack: Duration,
+    timer: Instant,
+}
+
+impl Budget {
+    /// A zero penalty means unlimited.
+    pub fn new(burst: Option<NonZero<u32>>, penalty: Duration) -> Self {
+        let burst = burst.map_or(1, NonZero::get);
+        Self {
+            penalty,
+            slack: penalty.saturating_mul(burst - 1),
+            timer: Instant::now(),
+        }
+    }
+
+    /// How long until we may send.
+    fn delay(&self, now: Instant) -> Duration {
+        if self.penalty.is_zero() {
+            return Duration::ZERO;
+        }
+        self.timer
+            .saturating_duration_since(now)
+            .saturating_sub(self.slack)
+    }
+
+    fn spend(&mut self, now: Instant) {
+        self.timer = self.timer.max(now) + self.penalty;
+    }
+}
+
+/// Number of messages waiting in each priority class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Queued {
+    pub critical: usize,
+    pub interactive: usize,
+    /// lines of moose art.
+    pub bulk: usize,
+}
+
+impl std::fmt::Display for Queued {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "critical: {}, interactive: {}, bulk: {}",
+            self.critical, self.interactive, self.bulk
+        )
+    }
+}
+
 #[derive(Clone)]
 pub struct Sender {
+    critical: mpsc::Sender<Message>,
     msg: mpsc::Sender<Message>,
-    moose: mpsc::Sender<Message>,
+    moose: Arc<MooseQueue>,
+    labels: Arc<Labels>,
 }
 
 impl Sender {
+    fn channel(&self, m: &Message) -> &mpsc::Sender<Message> {
+        match Priority::of(m) {
+            Priority::Critical => &self.critical,
+            _ => &self.msg,
+        }
+    }
+
     pub async fn send(&self, m: Message) {
-        let _ = self.msg.send(m).await;
+        let _ = self.channel(&m).send(m).await;
     }
 
     pub fn lossy_send(&self, m: Message) {
-        let _ = self.msg.try_send(m);
+        let _ = self.channel(&m).try_send(m);
+    }
+
+    /// Queue all the lines of one moose for target, asked for by requester.
+    pub fn send_moose(
+        &self,
+        target: &str,
+        requester: &str,
+        lines: Vec<Message>,
+    ) -> Result<(), MooseError> {
+        self.moose.push(target, requester, lines)
+    }
+
+    /// Label moose lines from now on; the server acknowledged `labeled-response`.
+    pub fn enable_labels(&self) {
+        self.labels.enabled.store(true, Ordering::Relaxed);
     }
 
-    pub fn send_moose(&self, m: Message) {
-        let _ = self.moose.try_send(m);
+    /// The server echoed a labeled line back to us, so it was delivered.
+    pub fn echoed(&self, label: &str) {
+        self.labels.sent.lock().unwrap().remove(label);
+    }
+
+    /// The server refused to deliver to target: stop its moose.
+    ///
+    /// Returns who asked for the moose, the first time a labeled moose line is rejected.
+    pub fn rejected(&self, label: Option<&str>, target: &str) -> Option<String> {
+        let mut sent = self.labels.sent.lock().unwrap();
+        let line = label.and_then(|l| sent.remove(l));
+        let target = line
+            .as_ref()
+            .map_or_else(|| target.to_lowercase(), |l| l.target.clone());
+        // the rest of the moose will be rejected too; only report it once.
+        sent.retain(|_, s| s.target != target);
+        self.moose.stop(&target);
+        line.and_then(|l| l.requester)
+    }
+
+    /// Cancel all moose for target; false if there were none.
+    pub fn stop_moose(&self, target: &str) -> bool {
+        self.moose.stop(target)
+    }
+
+    pub fn queued(&self) -> Queued {
+        let len = |c: &mpsc::Sender<Message>| c.max_capacity() - c.capacity();
+        Queued {
+            critical: len(&self.critical),
+            interactive: len(&self.msg),
+            bulk: self.moose.lines(),
+        }
     }
 }
 
 pub struct Receiver {
+    critical_r: mpsc::Receiver<Message>,
     msg_r: mpsc::Receiver<Message>,
-    moose_r: mpsc::Receiver<Message>,
+    moose: Arc<MooseQueue>,
+    labels: Arc<Labels>,
+}
+
+#[cfg(test)]
+impl Receiver {
+    /// Everything queued so far, in priority order.
+    pub fn drain(&mut self) -> Vec<Message> {
+        let mut msgs = vec![];
+        while let Ok(m) = self.critical_r.try_recv() {
+            msgs.push(m);
+        }
+        while let Ok(m) = self.msg_r.try_recv() {
+            msgs.push(self.labels.reply(m));
+        }
+        while let Some(line) = self.moose.pop() {
+            msgs.push(self.labels.tag(line));
+        }
+        msgs
+    }
 }
 
 pub fn create_send_recv_pair() -> (Sender, Receiver) {
+    let (critical, critical_r) = mpsc::channel(16);
     let (msg, msg_r) = mpsc::channel(64);
-    let (moose, moose_r) = mpsc::channel(64);
-    (Sender { msg, moose }, Receiver { msg_r, moose_r })
+    let moose = Arc::new(MooseQueue::default());
+    let labels = Arc::new(Labels::default());
+    (
+        Sender {
+            critical,
+            msg,
+            moose: moose.clone(),
+            labels: labels.clone(),
+        },
+        Receiver {
+            critical_r,
+            msg_r,
+            moose,
+            labels,
+        },
+    )
 }
 
+type Sink = SplitSink<Connection<Codec>, Message>;
+
+/// Send messages by priority.
+///
+/// Critical messages are sent immediately, though they still count against the connection budget.
+/// Interactive messages wait for the connection budget, and moose art additionally waits for
+/// its own bulk budget, so it can't use up the connection budget users need for replies.
+///
+/// Once stopped, we send any remaining critical messages and a QUIT with `quit` as the reason,
+/// giving up after `deadline`. The sink is returned so the connection can be shut down.
 pub fn sender_task(
-    send_burst: Option<NonZero<u32>>,
-    send_delay: Duration,
-    mut send: SplitSink<Connection<Codec>, Message>,
+    mut connection: Budget,
+    mut bulk: Budget,
+    mut send: Sink,
     recv: Receiver,
     stop_token: CancellationToken,
-) -> JoinHandle<()> {
-    let interval = if send_delay.is_zero() {
-        None
-    } else {
-        let send_burst = send_burst.unwrap_or_else(|| NonZero::<u32>::new(1).unwrap());
-        let rl = RateLimiter::direct(
-            Quota::with_period(send_delay)
-                .unwrap()
-                .allow_burst(send_burst),
-        );
-        Some(rl)
-    };
+    quit: Option<String>,
+    deadline: Duration,
+) -> JoinHandle<Sink> {
     let Receiver {
+        mut critical_r,
         mut msg_r,
-        mut moose_r,
+        moose,
+        labels,
     } = recv;
     tokio::task::spawn(async move {
         let _dropg = stop_token.drop_guard_ref();
-        while let Some(msg) = tokio::select! {
-            biased;
-            _ = stop_token.cancelled() => None,
-            m = msg_r.recv() => m,
-            m = moose_r.recv() => m,
-        } {
-            if let Some(i) = interval.as_ref() {
-                i.until_ready().await;
+        // a critical message that arrived while waiting.
+        let mut urgent = None;
+        // an interactive message waiting on the connection budget.
+        let mut pending = None;
+        let mut quit_sent = false;
+        while !stop_token.is_cancelled() {
+            if pending.is_none() {
+                pending = msg_r.try_recv().ok();
             }
-            if let Err(e) = send.send(msg).await {
-                eprintln!("ERR: [task/sender] IO error: {e}");
-                break;
+            let now = Instant::now();
+            let ready = connection.delay(now).is_zero();
+            let critical = urgent.take().or_else(|| critical_r.try_recv().ok());
+            let next = match critical {
+                Some(m) => Some((m, Priority::Critical)),
+                None if ready && pending.is_some() => pending
+                    .take()
+                    .map(|m| (labels.reply(m), Priority::Interactive)),
+                None if ready && bulk.delay(now).is_zero() => {
+                    moose.pop().map(|l| (labels.tag(l), Priority::Bulk))
+                }
+                None => None,
             };
+            if let Some((msg, priority)) = next {
+                connection.spend(now);
+                if priority == Priority::Bulk {
+                    bulk.spend(now);
+                }
+                quit_sent |= matches!(msg.command, Command::QUIT(_));
+                if let Err(e) = send.send(msg).await {
+                    eprintln!("ERR: [task/sender] IO error: {e}");
+                    return send;
+                };
+                continue;
+            }
+
+            let wait = if pending.is_some() {
+                connection.delay(now)
+            } else {
+                connection.delay(now).max(bulk.delay(now))
+            };
+            tokio::select! {
+                biased;
+                _ = stop_token.cancelled() => break,
+                m = critical_r.recv() => match m {
+                    Some(m) => urgent = Some(m),
+                    None => break,
+                },
+                m = msg_r.recv(), if pending.is_none() => match m {
+                    Some(m) => pending = Some(m),
+                    None => break,
+                },
+                // moose may be waiting on their budget, or may not have arrived yet.
+                _ = time::sleep(wait), if !wait.is_zero() => (),
+                _ = moose.ready.notified(), if pending.is_none() && wait.is_zero() => (),
+            }
         }
+
         eprintln!("INFO: [task/sender] Shutting down.");
+        let drain = async {
+            let critical = urgent
+                .into_iter()
+                .chain(std::iter::from_fn(|| critical_r.try_recv().ok()));
+            for msg in critical {
+                quit_sent |= matches!(msg.command, Command::QUIT(_));
+                send.feed(msg).await?;
+            }
+            if !quit_sent {
+                send.feed(Command::QUIT(quit).into()).await?;
+            }
+            send.flush().await
+        };
+        match time::timeout(deadline, drain).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => eprintln!("WARN: [task/sender] Failed to send QUIT: {e}"),
+            Err(_) => eprintln!("WARN: [task/sender] Timed out sending QUIT."),
+        }
+        send
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZero,
+        time::{Duration, Instant},
+    };
+
+    use irc::proto::{Command, Message};
+
+    use super::{Budget, MAX_MOOSE_LINES, MooseError, Queued, create_send_recv_pair};
+
+    fn moose(target: &str, name: &str, height: usize) -> Vec<Message> {
+        (0..height)
+            .map(|i| Command::PRIVMSG(target.to_owned(), format!("{name} {i}")).into())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let (sendo, mut recvo) = create_send_recv_pair();
+        sendo
+            .send_moose("#a", "someone", moose("#a", "a1", 2))
+            .unwrap();
+        sendo
+            .send_moose("#a", "someone", moose("#a", "a2", 1))
+            .unwrap();
+        sendo
+            .send_moose("#b", "someone", moose("#b", "b1", 2))
+            .unwrap();
+        let lines = recvo
+            .drain()
+            .into_iter()
+            .map(|m| match m.command {
+                Command::PRIVMSG(_, line) => line,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["a1 0", "b1 0", "a1 1", "b1 1", "a2 0"]);
+    }
+
+    #[test]
+    fn limits() {
+        let (sendo, mut recvo) = create_send_recv_pair();
+        assert_eq!(
+            sendo.send_moose("#a", "someone", moose("#a", "tall", MAX_MOOSE_LINES + 1)),
+            Err(MooseError::TooTall(MAX_MOOSE_LINES + 1))
+        );
+        for _ in 0..3 {
+            sendo
+                .send_moose("#a", "someone", moose("#a", "a", 1))
+                .unwrap();
+        }
+        assert_eq!(
+            sendo.send_moose("#A", "someone", moose("#a", "a", 1)),
+            Err(MooseError::Full)
+        );
+        sendo
+            .send_moose("#b", "someone", moose("#b", "b", 1))
+            .unwrap();
+
+        assert!(sendo.stop_moose("#A"));
+        assert!(!sendo.stop_moose("#a"));
+        assert_eq!(recvo.drain(), moose("#b", "b", 1));
+    }
+
+    #[test]
+    fn budget() {
+        let now = Instant::now();
+        let mut b = Budget::new(NonZero::new(3), Duration::from_secs(2));
+        b.timer = now;
+        for _ in 0..3 {
+            assert!(b.delay(now).is_zero());
+            b.spend(now);
+        }
+        assert_eq!(b.delay(now), Duration::from_secs(2));
+        assert!(b.delay(now + Duration::from_secs(2)).is_zero());
+
+        let mut unlimited = Budget::new(None, Duration::ZERO);
+        unlimited.spend(now);
+        assert!(unlimited.delay(now).is_zero());
+    }
+
+    #[test]
+    fn priorities() {
+        let (sendo, mut recvo) = create_send_recv_pair();
+        sendo
+            .send_moose("#a", "someone", moose("#a", "a", 1))
+            .unwrap();
+        sendo.lossy_send(Command::PRIVMSG("#a".to_owned(), "hi".to_owned()).into());
+        sendo.lossy_send(Command::PONG("x".to_owned(), None).into());
+        assert_eq!(
+            sendo.queued(),
+            Queued {
+                critical: 1,
+                interactive: 1,
+                bulk: 1
+            }
+        );
+        assert_eq!(
+            recvo.drain(),
+            vec![
+                Command::PONG("x".to_owned(), None).into(),
+                Command::PRIVMSG("#a".to_owned(), "hi".to_owned()).into(),
+                moose("#a", "a", 1).remove(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let (sendo, recvo) = create_send_recv_pair();
+        sendo.enable_labels();
+        sendo
+            .send_moose("#a", "someone", moose("#a", "a", 3))
+            .unwrap();
+        let line = recvo.labels.tag(recvo.moose.pop().unwrap());
+        let label = line.tags[0].value.clone().unwrap();
+        let echo = recvo.labels.tag(recvo.moose.pop().unwrap());
+        sendo.echoed(echo.tags[0].value.as_deref().unwrap());
+
+        assert_eq!(
+            sendo.rejected(Some(&label), "#a"),
+            Some("someone".to_owned())
+        );
+        assert_eq!(sendo.rejected(Some(&label), "#a"), None);
+        assert_eq!(sendo.queued().bulk, 0);
+
+        // replies are labeled too, but nobody asked for them.
+        let reply = recvo
+            .labels
+            .reply(Command::PRIVMSG("#b".to_owned(), "hi".to_owned()).into());
+        let label = reply.label().unwrap();
+        assert_eq!(label, "m2");
+        assert_eq!(sendo.rejected(Some(label), "#b"), None);
+        assert!(recvo.labels.sent.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::header::{CACHE_CONTROL, ETAG, HeaderMap};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{backend::SearchPage, debug};
+
+/// Moose names that resolve to a different moose on every request; these are never cached.
+pub const UNCACHEABLE: &[&str] = &["random", "latest", "oldest"];
+
+/// Whether moose is one of the [`UNCACHEABLE`] names, in any case.
+pub fn uncacheable(moose: &str) -> bool {
+    UNCACHEABLE.iter().any(|u| u.eq_ignore_ascii_case(moose))
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry<V> {
+    key: String,
+    value: V,
+    etag: Option<String>,
+    expires: SystemTime,
+    #[serde(skip, default = "SystemTime::now")]
+    last_used: SystemTime,
+}
+
+pub enum Lookup<V> {
+    /// Entry is within its max-age.
+    Fresh(V),
+    /// Entry is expired, but can be revalidated with If-None-Match.
+    Stale {
+        value: V,
+        etag: String,
+    },
+    Miss,
+}
+
+/// How long a response may be stored, derived from the upstream response headers.
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub no_store: bool,
+    pub max_age: Option<Duration>,
+    pub etag: Option<String>,
+}
+
+impl Policy {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut policy = Policy {
+            etag: headers
+                .get(ETAG)
+                .and_then(|e| e.to_str().ok())
+                .map(ToOwned::to_owned),
+            ..Default::default()
+        };
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_ascii_lowercase());
+        for directive in directives {
+            match directive.split_once('=') {
+                Some(("max-age", secs)) => {
+                    policy.max_age = secs.trim_matches('"').parse().ok().map(Duration::from_secs)
+                }
+                None if directive == "no-store" || directive == "private" => policy.no_store = true,
+                None if directive == "no-cache" => policy.max_age = Some(Duration::ZERO),
+                _ => (),
+            }
+        }
+        policy
+    }
+}
+
+/// A TTL and size bounded cache of moose2 responses, optionally persisted to a directory.
+///
+/// Entries are evicted least recently used first once `max_entries` is reached.
+/// The on-disk copy is a write-through mirror that is consulted on a memory miss,
+/// so cached moose survive restarts.
+pub struct Cache<V> {
+    entries: Mutex<HashMap<String, Entry<V>>>,
+    ttl: Duration,
+    max_entries: usize,
+    dir: Option<PathBuf>,
+}
+
+/// File an entry is stored in; a 64-bit FNV-1a hash of the key, which is stable across builds.
+fn file_name(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf29ce484222325_u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}.json")
+}
+
+/// Remove files we won't read back, then the least recently written beyond `max_entries`.
+fn prune<V: DeserializeOwned>(dir: &Path, max_entries: usize) {
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    let mut kept = vec![];
+    for file in files.filter_map(Result::ok) {
+        let path = file.path();
+        let name = file.file_name();
+        let entry = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Entry<V>>(&bytes).ok())
+            // left over temporary files and keys named by an older hash are unreadable.
+            .filter(|e| name.to_str() == Some(file_name(&e.key).as_str()))
+            .filter(|e| e.expires > now || e.etag.is_some());
+        let modified = file.metadata().and_then(|m| m.modified());
+        match (entry, modified) {
+            (Some(_), Ok(modified)) => kept.push((modified, path)),
+            _ => {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+    kept.sort_unstable_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, path) in kept.into_iter().skip(max_entries) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+impl<V> Cache<V>
+where
+    V: Clone + Serialize + DeserializeOwned,
+{
+    /// A `ttl` or `max_entries` of zero disables the cache.
+    ///
+    /// Files in `dir` beyond these bounds are removed.
+    pub fn new(ttl: Duration, max_entries: usize, dir: Option<PathBuf>) -> Self {
+        if let Some(dir) = dir.as_deref() {
+            let max_entries = if ttl.is_zero() { 0 } else { max_entries };
+            prune::<V>(dir, max_entries);
+        }
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+            dir,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.max_entries != 0
+    }
+
+    pub async fn lookup(&self, key: &str) -> Lookup<V> {
+        if !self.enabled() {
+            return Lookup::Miss;
+        }
+        let now = SystemTime::now();
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.get_mut(key).map(|e| {
+                e.last_used = now;
+                e.clone()
+            })
+        };
+        let entry = match entry {
+            Some(e) => e,
+            None => match self.load(key).await {
+                Some(e) => {
+                    self.insert_entry(e.clone()).await;
+                    e
+                }
+                None => return Lookup::Miss,
+            },
+        };
+        if entry.expires > now {
+            debug!("DEBUG: [webreq/cache] HIT {key}");
+            Lookup::Fresh(entry.value)
+        } else if let Some(etag) = entry.etag {
+            debug!("DEBUG: [webreq/cache] STALE {key}");
+            Lookup::Stale {
+                value: entry.value,
+                etag,
+            }
+        } else {
+            self.remove(key).await;
+            Lookup::Miss
+        }
+    }
+
+    /// Store a response according to its cache policy.
+    pub async fn insert(&self, key: &str, value: V, policy: Policy) {
+        if !self.enabled() || policy.no_store {
+            return;
+        }
+        let max_age = policy.max_age.map_or(self.ttl, |age| age.min(self.ttl));
+        // nothing to revalidate with, so there's no point in keeping it.
+        if max_age.is_zero() && policy.etag.is_none() {
+            self.remove(key).await;
+            return;
+        }
+        let now = SystemTime::now();
+        self.insert_entry(Entry {
+            key: key.to_owned(),
+            value,
+            etag: policy.etag,
+            expires: now + max_age,
+            last_used: now,
+        })
+        .await;
+    }
+
+    /// Mark a stale entry as fresh again after a `304 Not Modified`.
+    pub async fn refresh(&self, key: &str, policy: Policy) {
+        let entry = self.entries.lock().unwrap().get(key).cloned();
+        if let Some(entry) = entry {
+            let etag = policy.etag.clone().or(entry.etag);
+            self.insert(key, entry.value, Policy { etag, ..policy })
+                .await;
+        }
+    }
+
+    async fn insert_entry(&self, entry: Entry<V>) {
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            let evicted = if entries.len() >= self.max_entries && !entries.contains_key(&entry.key)
+            {
+                let lru = entries
+                    .values()
+                    .min_by_key(|e| e.last_used)
+                    .map(|e| e.key.clone());
+                lru.and_then(|k| entries.remove(&k)).map(|e| e.key)
+            } else {
+                None
+            };
+            entries.insert(entry.key.clone(), entry.clone());
+            evicted
+        };
+        if let Some(evicted) = evicted {
+            self.remove_file(&evicted).await;
+        }
+        self.store(&entry).await;
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+        self.remove_file(key).await;
+    }
+
+    async fn load(&self, key: &str) -> Option<Entry<V>> {
+        let path = self.dir.as_ref()?.join(file_name(key));
+        let bytes = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice::<Entry<V>>(&bytes)
+            .ok()
+            // hash collision; treat as a miss.
+            .filter(|e| e.key == key)
+    }
+
+    async fn store(&self, entry: &Entry<V>) {
+        let Some(dir) = self.dir.as_ref() else {
+            return;
+        };
+        let bytes = serde_json::to_vec(entry).expect("Cache entries should always serialize.");
+        let name = file_name(&entry.key);
+        let r: u64 = rand::random();
+        let tmp = dir.join(format!(".{name}.{r:x}"));
+        let written = match tokio::fs::write(&tmp, bytes).await {
+            Ok(()) => tokio::fs::rename(&tmp, dir.join(name)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(tmp).await;
+            eprintln!("WARN: [webreq/cache] Failed to persist cache entry: {e}");
+        }
+    }
+
+    async fn remove_file(&self, key: &str) {
+        if let Some(dir) = self.dir.as_ref() {
+            let _ = tokio::fs::remove_file(dir.join(file_name(key))).await;
+        }
+    }
+}
+
+/// All the caches used by the moose2 API helpers.
+pub struct MooseCache {
+    /// moose name -> resolved (url encoded) moose name.
+    pub resolve: Cache<String>,
+    /// resolved moose name -> IRC art.
+    pub irclines: Cache<String>,
+    /// `<page>:<search query>` -> search results.
+    pub search: Cache<SearchPage>,
+}
+
+impl MooseCache {
+    pub fn new(ttl: Duration, max_entries: usize, dir: Option<PathBuf>) -> Self {
+        let subdir = |name: &str| {
+            dir.as_ref().map(|d| d.join(name)).and_then(|d| {
+                std::fs::create_dir_all(&d)
+                    .inspect_err(|e| {
+                        eprintln!("WARN: [webreq/cache] Cannot create cache dir {d:?}: {e}")
+                    })
+                    .ok()
+                    .map(|_| d)
+            })
+        };
+        Self {
+            resolve: Cache::new(ttl, max_entries, subdir("resolve")),
+            irclines: Cache::new(ttl, max_entries, subdir("irc")),
+            search: Cache::new(ttl, max_entries, subdir("search")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::header::{CACHE_CONTROL, ETAG, HeaderMap, HeaderValue};
+
+    use super::{Cache, Lookup, Policy, file_name, uncacheable};
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=60"),
+        );
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        let policy = Policy::from_headers(&headers);
+        assert_eq!(policy.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(policy.etag.as_deref(), Some("\"abc\""));
+        assert!(!policy.no_store);
+
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        assert!(Policy::from_headers(&headers).no_store);
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        assert_eq!(Policy::from_headers(&headers).max_age, Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn evicts_and_revalidates() {
+        let cache = Cache::<String>::new(Duration::from_secs(60), 2, None);
+        cache.insert("a", "1".to_owned(), Policy::default()).await;
+        cache.insert("b", "2".to_owned(), Policy::default()).await;
+        assert!(matches!(cache.lookup("a").await, Lookup::Fresh(v) if v == "1"));
+        // b is least recently used.
+        cache.insert("c", "3".to_owned(), Policy::default()).await;
+        assert!(matches!(cache.lookup("b").await, Lookup::Miss));
+        assert!(matches!(cache.lookup("c").await, Lookup::Fresh(_)));
+
+        let no_cache = || Policy {
+            max_age: Some(Duration::ZERO),
+            etag: Some("x".to_owned()),
+            no_store: false,
+        };
+        cache.insert("d", "4".to_owned(), no_cache()).await;
+        assert!(matches!(cache.lookup("d").await, Lookup::Stale { etag, .. } if etag == "x"));
+        cache.refresh("d", Policy::default()).await;
+        assert!(matches!(cache.lookup("d").await, Lookup::Fresh(v) if v == "4"));
+    }
+
+    #[test]
+    fn names() {
+        // file names must not change between builds, or we lose the cache.
+        assert_eq!(file_name(""), "cbf29ce484222325.json");
+        assert_eq!(file_name("moose"), file_name("moose"));
+        assert_ne!(file_name("moose"), file_name("Moose"));
+        assert!(uncacheable("Random"));
+        assert!(!uncacheable("randomoose"));
+    }
+
+    #[tokio::test]
+    async fn prunes_on_startup() {
+        let tmp = TempDir::new("moose-cache");
+        let dir = &tmp.0;
+        let cache = Cache::<String>::new(Duration::from_secs(60), 3, Some(dir.clone()));
+        for key in ["a", "b", "c"] {
+            cache.insert(key, key.to_owned(), Policy::default()).await;
+            // modification times have to differ.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        std::fs::write(dir.join("0123456789abcdef.json"), b"{}").unwrap();
+        std::fs::write(dir.join(".tmp.json.1"), b"").unwrap();
+
+        let files = || std::fs::read_dir(dir).unwrap().count();
+        let cache = Cache::<String>::new(Duration::from_secs(60), 2, Some(dir.clone()));
+        assert_eq!(files(), 2);
+        assert!(matches!(cache.lookup("a").await, Lookup::Miss));
+        assert!(matches!(cache.lookup("c").await, Lookup::Fresh(v) if v == "c"));
+
+        Cache::<String>::new(Duration::ZERO, 2, Some(dir.clone()));
+        assert_eq!(files(), 0);
+    }
+}
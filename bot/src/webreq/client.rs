@@ -0,0 +1,288 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use reqwest::{
+    Response, StatusCode,
+    header::{IF_NONE_MATCH, RETRY_AFTER},
+};
+
+use crate::{debug, handlers::ircstate::APP_NAME};
+
+use super::ResolveError;
+
+/// How many times a failed GET is retried before giving up.
+const MAX_RETRIES: u32 = 2;
+/// First retry delay; doubled for every retry after.
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+/// Don't honour Retry-After if upstream wants us to wait longer than this.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3);
+/// Consecutive failed requests before we stop talking to moose2.
+const BREAKER_THRESHOLD: u32 = 5;
+/// How long to stop talking to moose2 before probing it again.
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+/// Stops sending requests to moose2 after repeated failures.
+///
+/// Once open, a single probe request is allowed through after the cooldown;
+/// if it succeeds the breaker closes, otherwise it opens for another cooldown.
+pub struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            state: Mutex::new(BreakerState::default()),
+            threshold,
+            cooldown,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), ResolveError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match state.open_until {
+            None => Ok(()),
+            Some(until) if now < until => Err(ResolveError::CircuitOpen),
+            // half open; only one request gets to see if moose2 is back.
+            Some(_) => match state.probe_started {
+                Some(probe) if now.duration_since(probe) < self.cooldown => {
+                    Err(ResolveError::CircuitOpen)
+                }
+                _ => {
+                    state.probe_started = Some(now);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    pub fn success(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    pub fn failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.probe_started.is_some() || state.failures >= self.threshold {
+            if state.open_until.is_none() {
+                eprintln!("WARN: [webreq] moose2 appears to be down; pausing requests.");
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+            state.probe_started = None;
+        }
+    }
+}
+
+/// HTTP client for the moose2 API with retries and a circuit breaker.
+pub struct MooseClient {
+    http: reqwest::Client,
+    breaker: CircuitBreaker,
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let jitter = Duration::from_millis(rand::random::<u64>() % 100);
+    BACKOFF_BASE * 2u32.pow(attempt) + jitter
+}
+
+/// Retry-After as delta-seconds, or an HTTP-date.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // a date in the past means now.
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+impl MooseClient {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .user_agent(APP_NAME)
+                .timeout(Duration::from_secs(5))
+                .build()
+                .expect("FATAL: [irc] Expected to build HTTP client."),
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN),
+        }
+    }
+
+    /// GET a moose2 url, retrying transient failures.
+    ///
+    /// Any response that isn't a 5xx or 429 is returned to the caller as is.
+    pub async fn get(&self, url: &str, etag: Option<&str>) -> Result<Response, ResolveError> {
+        self.breaker.check()?;
+        let mut attempt = 0;
+        loop {
+            let mut req = self.http.get(url);
+            if let Some(etag) = etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            let (err, wait) = match req.send().await {
+                Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let wait = retry_after(&res).unwrap_or_else(|| backoff(attempt));
+                    (ResolveError::RateLimited, wait)
+                }
+                Ok(res) if res.status().is_server_error() => (
+                    ResolveError::ServerError(res.status().as_u16()),
+                    backoff(attempt),
+                ),
+                Ok(res) => {
+                    self.breaker.success();
+                    return Ok(res);
+                }
+                Err(e) => {
+                    let err = ResolveError::from(e);
+                    if let ResolveError::Reqwest(e) = &err {
+                        eprintln!("WARN: [webreq] Unexpected HTTP error: {e}");
+                    }
+                    (err, backoff(attempt))
+                }
+            };
+            debug!("DEBUG: [webreq] GET {url} failed (attempt {attempt}): {err:?}");
+            if attempt >= MAX_RETRIES || !err.is_transient() || wait > MAX_RETRY_AFTER {
+                // being rate limited means moose2 is up.
+                if !matches!(err, ResolveError::RateLimited) {
+                    self.breaker.failure();
+                }
+                return Err(err);
+            }
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use reqwest::StatusCode;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{CircuitBreaker, MooseClient};
+    use crate::webreq::{ResolveError, cache::MooseCache, get_irclines};
+
+    /// Answer one request per connection with each response in turn; returns the url and a request count.
+    async fn serve(responses: &[&str]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let responses = responses.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let n = tcp.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                count.fetch_add(1, Ordering::Relaxed);
+                tcp.write_all(response.as_bytes()).await.unwrap();
+                tcp.shutdown().await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    fn too_many(retry_after: &str) -> String {
+        format!(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: {retry_after}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+    }
+
+    #[tokio::test]
+    async fn retries_with_backoff() {
+        let (url, requests) = serve(&[UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let res = MooseClient::new().get(&url, None).await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+
+        let (url, requests) = serve(&[UNAVAILABLE, UNAVAILABLE, UNAVAILABLE]).await;
+        let err = MooseClient::new().get(&url, None).await.unwrap_err();
+        assert!(matches!(err, ResolveError::ServerError(503)), "{err:?}");
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_after() {
+        // a date in the past lets us retry right away.
+        let (url, requests) = serve(&[&too_many("Wed, 21 Oct 2015 07:28:00 GMT"), OK]).await;
+        assert!(MooseClient::new().get(&url, None).await.is_ok());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+
+        // too long to wait for.
+        let (url, requests) = serve(&[&too_many("60")]).await;
+        let err = MooseClient::new().get(&url, None).await.unwrap_err();
+        assert!(matches!(err, ResolveError::RateLimited), "{err:?}");
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn status_errors() {
+        let (url, requests) = serve(&[NOT_FOUND, NOT_FOUND]).await;
+        let client = MooseClient::new();
+        // client errors are the caller's to handle, without retrying.
+        let res = client.get(&url, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let cache = MooseCache::new(Duration::ZERO, 0, None);
+        let err = get_irclines(&client, &url, &cache, "test%20moose")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No such moose: test moose");
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn breaker_opens_and_probes() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert!(breaker.check().is_ok());
+        breaker.failure();
+        assert!(breaker.check().is_ok());
+        breaker.failure();
+        assert!(breaker.check().is_err());
+
+        // cooldown elapsed; only one probe is let through.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        // failed probe reopens immediately.
+        breaker.failure();
+        assert!(breaker.check().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        breaker.success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+}
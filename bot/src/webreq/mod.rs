@@ -0,0 +1,233 @@
+use cache::{Cache, Lookup, MooseCache, Policy, uncacheable};
+use client::MooseClient;
+use percent_encoding::{PercentEncode, percent_decode_str};
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::backend::{SearchHit, SearchPage};
+
+pub mod cache;
+pub mod client;
+
+#[derive(Deserialize)]
+struct ResolveRequest {
+    status: String,
+    msg: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    #[serde(default)]
+    pages: usize,
+    result: Vec<SearchMoose>,
+}
+
+#[derive(Deserialize)]
+struct SearchMoose {
+    page: usize,
+    moose: SearchMooseName,
+}
+
+#[derive(Deserialize)]
+struct SearchMooseName {
+    name: String,
+}
+
+impl ResolveRequest {
+    fn into_result(self) -> Result<String, ResolveError> {
+        if self.status == "error" {
+            Err(ResolveError::Upstream(self.msg))
+        } else {
+            // is percent encoded for us by upstream, uses same code as Location: redirect for random/latest/oldest.
+            Ok(self.msg)
+        }
+    }
+}
+
+impl From<ResolveRequest> for ResolveError {
+    fn from(value: ResolveRequest) -> Self {
+        Self::Upstream(value.msg)
+    }
+}
+
+/// Errors from the moose2 API; the Display impl is shown to IRC users as is.
+#[derive(thiserror::Error, Debug)]
+pub enum ResolveError {
+    #[error("moose2 took too long to respond; try again later.")]
+    Timeout(#[source] reqwest::Error),
+    #[error("Could not connect to moose2; try again later.")]
+    Connect(#[source] reqwest::Error),
+    #[error("moose2 sent a response we could not understand.")]
+    InvalidResponse(#[source] reqwest::Error),
+    #[error("moose2 is having problems (HTTP {0}); try again later.")]
+    ServerError(u16),
+    #[error("moose2 is rate limiting us; try again in a bit.")]
+    RateLimited,
+    #[error("No such moose: {0}")]
+    NotFound(String),
+    #[error("moose2 is down; try again later.")]
+    CircuitOpen,
+    #[error("Failed to talk to moose2.")]
+    Reqwest(#[source] reqwest::Error),
+    #[error("Failed to read moose from disk.")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Upstream(String),
+}
+
+impl From<reqwest::Error> for ResolveError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e)
+        } else if e.is_connect() {
+            Self::Connect(e)
+        } else if e.is_decode() {
+            Self::InvalidResponse(e)
+        } else {
+            Self::Reqwest(e)
+        }
+    }
+}
+
+impl ResolveError {
+    /// Whether retrying the same request could succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Timeout(_) | Self::Connect(_) | Self::ServerError(_) | Self::RateLimited
+        )
+    }
+
+    /// Map an unsuccessful response for a given moose to an error.
+    async fn from_response(res: Response, moose: &str) -> Self {
+        if res.status() == StatusCode::NOT_FOUND {
+            return Self::NotFound(moose.to_owned());
+        }
+        match res.json::<ResolveRequest>().await {
+            Ok(r) => r.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+pub fn urlencode(q: &[u8]) -> PercentEncode<'_> {
+    percent_encoding::percent_encode(q, percent_encoding::NON_ALPHANUMERIC)
+}
+
+enum Fetched<V> {
+    Cached(V),
+    Response(Response, Policy),
+}
+
+/// GET a url, answering from the cache when possible and revalidating stale entries using their ETag.
+async fn cached_get<V>(
+    client: &MooseClient,
+    url: String,
+    cache: &Cache<V>,
+    key: &str,
+) -> Result<Fetched<V>, ResolveError>
+where
+    V: Clone + Serialize + DeserializeOwned,
+{
+    let (stale, etag) = match cache.lookup(key).await {
+        Lookup::Fresh(value) => return Ok(Fetched::Cached(value)),
+        Lookup::Stale { value, etag } => (Some(value), Some(etag)),
+        Lookup::Miss => (None, None),
+    };
+    let res = client.get(&url, etag.as_deref()).await?;
+    let policy = Policy::from_headers(res.headers());
+    match stale {
+        Some(value) if res.status() == StatusCode::NOT_MODIFIED => {
+            cache.refresh(key, policy).await;
+            Ok(Fetched::Cached(value))
+        }
+        _ => Ok(Fetched::Response(res, policy)),
+    }
+}
+
+async fn resolve_response(res: Response, moose: &str) -> Result<String, ResolveError> {
+    if res.status() == StatusCode::NOT_FOUND {
+        return Err(ResolveError::NotFound(moose.to_owned()));
+    }
+    res.json::<ResolveRequest>().await?.into_result()
+}
+
+pub async fn resolve_moosename(
+    client: &MooseClient,
+    url: &str,
+    cache: &MooseCache,
+    moose: &str,
+) -> Result<String, ResolveError> {
+    let req_url = format!("{url}/api-helper/resolve/{}", urlencode(moose.as_bytes()));
+    if uncacheable(moose) {
+        let res = client.get(&req_url, None).await?;
+        return resolve_response(res, moose).await;
+    }
+    match cached_get(client, req_url, &cache.resolve, moose).await? {
+        Fetched::Cached(resolved) => Ok(resolved),
+        Fetched::Response(res, policy) => {
+            let resolved = resolve_response(res, moose).await?;
+            cache.resolve.insert(moose, resolved.clone(), policy).await;
+            Ok(resolved)
+        }
+    }
+}
+
+pub async fn get_irclines(
+    client: &MooseClient,
+    url: &str,
+    cache: &MooseCache,
+    moose: &str,
+) -> Result<String, ResolveError> {
+    match cached_get(client, format!("{url}/irc/{moose}"), &cache.irclines, moose).await? {
+        Fetched::Cached(lines) => Ok(lines),
+        Fetched::Response(res, policy) if res.status().is_success() => {
+            let lines = res.text().await?;
+            cache.irclines.insert(moose, lines.clone(), policy).await;
+            Ok(lines)
+        }
+        Fetched::Response(res, _) => {
+            // show the name as it was asked for, not as it goes in a url.
+            let name = percent_decode_str(moose).decode_utf8_lossy();
+            Err(ResolveError::from_response(res, &name).await)
+        }
+    }
+}
+
+// note this api should always succeed.
+pub async fn get_search(
+    client: &MooseClient,
+    url: &str,
+    cache: &MooseCache,
+    query: &str,
+    page: usize,
+) -> Result<SearchPage, ResolveError> {
+    let req_url = format!("{url}/search?p={page}&q={}", urlencode(query.as_bytes()));
+    let key = format!("{page}:{query}");
+    let (res, policy) = match cached_get(client, req_url, &cache.search, &key).await? {
+        Fetched::Cached(results) => return Ok(results),
+        Fetched::Response(res, policy) if res.status().is_success() => (res, policy),
+        Fetched::Response(res, _) => return Err(ResolveError::from_response(res, query).await),
+    };
+    let res = res.json::<SearchResult>().await?;
+    let hits = res
+        .result
+        .into_iter()
+        .map(|s| SearchHit {
+            name: s.moose.name,
+            page: s.page,
+        })
+        .collect::<Vec<_>>();
+    // older moose2 versions do not tell us how many pages there are.
+    let pages = res.pages.max(page + usize::from(!hits.is_empty()));
+    let results = SearchPage {
+        // moose2 only counts pages; a single page is all there is.
+        total: (page == 0 && pages <= 1).then_some(hits.len()),
+        pages,
+        hits,
+    };
+    if !results.hits.is_empty() {
+        cache.search.insert(&key, results.clone(), policy).await;
+    }
+    Ok(results)
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use irc::{codec::MAX_MESSAGE, Codec};
+use libfuzzer_sys::fuzz_target;
+use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = Codec::builder()
+        .max_line_length(MAX_MESSAGE)
+        .recover(true)
+        .build();
+    // feed the input in pieces, like reads from a socket.
+    let mut src = BytesMut::new();
+    for chunk in data.chunks(64) {
+        src.extend_from_slice(chunk);
+        while let Some(_message) = codec.decode(&mut src).expect("recovering codec never fails") {}
+    }
+    let _ = codec.decode_eof(&mut src);
+});
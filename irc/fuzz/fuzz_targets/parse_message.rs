@@ -0,0 +1,18 @@
+#![no_main]
+
+use irc::proto::{format, parse, MessageRef};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // one message per line, so the server corpus in irc/proto/corpus works as seeds.
+    for line in String::from_utf8_lossy(data).lines() {
+        let line = format!("{line}\r\n");
+        let borrowed = MessageRef::parse(&line).map(MessageRef::into_owned);
+        let Ok(message) = parse::message(&line) else {
+            continue;
+        };
+        assert_eq!(borrowed.unwrap(), message);
+        let formatted = format::message(message.clone());
+        assert_eq!(parse::message(&formatted).unwrap(), message);
+    }
+});
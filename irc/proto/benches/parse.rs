@@ -0,0 +1,38 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use irc_proto::{parse, MessageRef};
+
+const LINES: &[&str] = &[
+    "PING :irc.example.com\r\n",
+    ":dan!d@localhost PRIVMSG #chan :Hey what's up!\r\n",
+    "@batch=JQlhpjWY7SYaBPQtXAfUQh;msgid=UGnor4DBoafs6ge0UgsHF7-aVdnYMbjbdTf9eEHQmPKWA;time=2024-11-07T12:04:28.361Z :foo!~foo@F3FF3610.5A633F24.29800D3F.IP JOIN #pixelcove * :foo\r\n",
+    ":irc.example.com 005 moose CHANTYPES=# EXCEPTS INVEX CHANMODES=eIbq,k,flj,CFLMPQScgimnprstuz CHANLIMIT=#:120 PREFIX=(ov)@+ MAXLIST=bqeI:100 MODES=4 NETWORK=example KNOCK STATUSMSG=@+ CALLERID=g :are supported by this server\r\n",
+];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.bench_function("nom", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(parse::message(black_box(line)).unwrap());
+            }
+        })
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(MessageRef::parse(black_box(line)).unwrap());
+            }
+        })
+    });
+    group.bench_function("borrowed+into_owned", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(MessageRef::parse(black_box(line)).unwrap().into_owned());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,391 @@
+//! Group messages sent in batches.
+//!
+//! Reference: https://ircv3.net/specs/extensions/batch
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{tags, Command, Message, Tag};
+
+/// Either a message, or a batch nested in another batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Message(Message),
+    Batch(Batch),
+}
+
+/// A complete batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub reference: String,
+    /// batch type, e.g. `netsplit` or `chathistory`.
+    pub kind: String,
+    pub params: Vec<String>,
+    /// tags of the `BATCH +reference` message.
+    pub tags: Vec<Tag>,
+    pub items: Vec<Item>,
+}
+
+/// Batch types we know about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind<'a> {
+    /// https://ircv3.net/specs/batches/netsplit
+    Netsplit {
+        server1: &'a str,
+        server2: &'a str,
+    },
+    Netjoin {
+        server1: &'a str,
+        server2: &'a str,
+    },
+    /// https://ircv3.net/specs/extensions/chathistory
+    ChatHistory {
+        target: &'a str,
+    },
+    /// https://ircv3.net/specs/extensions/labeled-response
+    LabeledResponse,
+    Other(&'a str),
+}
+
+impl Batch {
+    pub fn kind(&self) -> Kind<'_> {
+        let param = |i: usize| self.params.get(i).map_or("", String::as_str);
+        match self.kind.as_str() {
+            "netsplit" => Kind::Netsplit {
+                server1: param(0),
+                server2: param(1),
+            },
+            "netjoin" => Kind::Netjoin {
+                server1: param(0),
+                server2: param(1),
+            },
+            "chathistory" => Kind::ChatHistory { target: param(0) },
+            "labeled-response" => Kind::LabeledResponse,
+            other => Kind::Other(other),
+        }
+    }
+
+    /// Label of the command this batch responds to.
+    pub fn label(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|t| t.key == tags::LABEL)
+            .and_then(|t| t.value.as_deref())
+            .filter(|l| !l.is_empty())
+    }
+
+    /// All messages in the batch, including nested batches, in the order they were received.
+    pub fn into_messages(self) -> Vec<Message> {
+        let mut messages = vec![];
+        for item in self.items {
+            match item {
+                Item::Message(m) => messages.push(m),
+                Item::Batch(b) => messages.extend(b.into_messages()),
+            }
+        }
+        messages
+    }
+}
+
+/// Batches an assembler keeps open at once, nested ones included.
+pub const MAX_OPEN: usize = 16;
+/// Messages an assembler keeps per batch.
+pub const MAX_ITEMS: usize = 4096;
+/// How long an assembler waits for a batch to end.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What the assembler has ready for us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Message(Message),
+    Batch(Batch),
+}
+
+struct Open {
+    batch: Batch,
+    /// reference of the batch this one is nested in, and our index in its items.
+    parent: Option<(String, usize)>,
+    started: Instant,
+    /// order batches were opened in, as instants can be equal.
+    serial: usize,
+}
+
+/// Collects batched messages until their outermost batch ends.
+///
+/// A server could open batches and never end them, so there are limits: when too many are open,
+/// the oldest is dropped, as is a batch with too many messages or one that is open too long.
+/// Messages that still reference a dropped batch pass through on their own.
+pub struct Assembler {
+    open: HashMap<String, Open>,
+    max_open: usize,
+    max_items: usize,
+    timeout: Duration,
+    dropped: usize,
+    opened: usize,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::with_limits(MAX_OPEN, MAX_ITEMS, TIMEOUT)
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(max_open: usize, max_items: usize, timeout: Duration) -> Self {
+        Self {
+            open: HashMap::new(),
+            max_open: max_open.max(1),
+            max_items,
+            timeout,
+            dropped: 0,
+            opened: 0,
+        }
+    }
+
+    /// Number of batches dropped for going over a limit, ever.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Forget a batch and every batch nested in it.
+    fn drop_batch(&mut self, reference: &str) {
+        if self.open.remove(reference).is_none() {
+            return;
+        }
+        self.dropped += 1;
+        let nested = self
+            .open
+            .iter()
+            .filter(|(_, o)| o.parent.as_ref().is_some_and(|(p, _)| p == reference))
+            .map(|(r, _)| r.clone())
+            .collect::<Vec<_>>();
+        for reference in nested {
+            self.drop_batch(&reference);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired = self
+            .open
+            .iter()
+            .filter(|(_, o)| now.saturating_duration_since(o.started) >= self.timeout)
+            .map(|(r, _)| r.clone())
+            .collect::<Vec<_>>();
+        for reference in expired {
+            self.drop_batch(&reference);
+        }
+    }
+
+    /// Number of batches that have started, but not ended.
+    pub fn pending(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Add a message; returns it, or the batch it completes, if it isn't part of an open batch.
+    pub fn push(&mut self, message: Message) -> Option<Event> {
+        let now = Instant::now();
+        self.expire(now);
+        let parent = message
+            .batch()
+            .filter(|r| self.open.contains_key(*r))
+            .map(str::to_owned);
+        match message.command {
+            Command::BATCH(ref reference, ref params) if reference.starts_with('+') => {
+                let reference = reference[1..].to_owned();
+                let (kind, params) = match params.split_first() {
+                    Some((kind, params)) => (kind.clone(), params.to_vec()),
+                    None => (String::new(), vec![]),
+                };
+                let tags = message
+                    .tags
+                    .into_iter()
+                    .filter(|t| t.key != tags::BATCH)
+                    .collect();
+                let batch = Batch {
+                    reference: reference.clone(),
+                    kind,
+                    params,
+                    tags,
+                    items: vec![],
+                };
+                if self.open.len() >= self.max_open {
+                    let oldest = self
+                        .open
+                        .iter()
+                        .min_by_key(|(_, o)| o.serial)
+                        .map(|(r, _)| r.clone());
+                    if let Some(oldest) = oldest {
+                        self.drop_batch(&oldest);
+                    }
+                }
+                // keep our place in the parent until we end.
+                let parent = parent.and_then(|p| {
+                    let items = &mut self.open.get_mut(&p)?.batch.items;
+                    items.push(Item::Batch(batch.clone()));
+                    Some((p, items.len() - 1))
+                });
+                self.opened += 1;
+                let open = Open {
+                    batch,
+                    parent,
+                    started: now,
+                    serial: self.opened,
+                };
+                self.open.insert(reference, open);
+                None
+            }
+            Command::BATCH(ref reference, _) if reference.starts_with('-') => {
+                let Some(open) = self.open.remove(&reference[1..]) else {
+                    return Some(Event::Message(message));
+                };
+                match open
+                    .parent
+                    .and_then(|(p, i)| self.open.get_mut(&p)?.batch.items.get_mut(i))
+                {
+                    Some(slot) => {
+                        *slot = Item::Batch(open.batch);
+                        None
+                    }
+                    None => Some(Event::Batch(open.batch)),
+                }
+            }
+            _ => match parent {
+                Some(parent) => {
+                    let open = self.open.get_mut(&parent)?;
+                    if open.batch.items.len() < self.max_items {
+                        open.batch.items.push(Item::Message(message));
+                        None
+                    } else {
+                        self.drop_batch(&parent);
+                        Some(Event::Message(message))
+                    }
+                }
+                None => Some(Event::Message(message)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn push_all(assembler: &mut Assembler, lines: &[&str]) -> Vec<Event> {
+        lines
+            .iter()
+            .filter_map(|l| assembler.push(parse::message(&format!("{l}\r\n")).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn netsplit() {
+        let mut assembler = Assembler::new();
+        let events = push_all(
+            &mut assembler,
+            &[
+                ":irc.host BATCH +yXNAbvnRHTRBv netsplit irc.hub other.host",
+                "@batch=yXNAbvnRHTRBv :aji!a@a QUIT :irc.hub other.host",
+                ":nick!u@h PRIVMSG #c :not batched",
+                "@batch=yXNAbvnRHTRBv :nenolod!a@a QUIT :irc.hub other.host",
+                ":irc.host BATCH -yXNAbvnRHTRBv",
+            ],
+        );
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], Event::Message(_)));
+        let Event::Batch(batch) = &events[1] else {
+            panic!("expected a batch: {events:?}");
+        };
+        assert_eq!(
+            batch.kind(),
+            Kind::Netsplit {
+                server1: "irc.hub",
+                server2: "other.host"
+            }
+        );
+        assert_eq!(batch.items.len(), 2);
+        assert_eq!(assembler.pending(), 0);
+    }
+
+    #[test]
+    fn nested() {
+        let mut assembler = Assembler::new();
+        let events = push_all(
+            &mut assembler,
+            &[
+                "@label=abc :irc.host BATCH +outer labeled-response",
+                "@batch=outer :irc.host BATCH +inner chathistory #c",
+                "@batch=inner :nick!u@h PRIVMSG #c :.moose",
+                "@batch=outer :irc.host NOTICE me :done",
+                ":irc.host BATCH -inner",
+                ":irc.host BATCH -outer",
+                "@batch=unknown :nick!u@h PRIVMSG #c :hi",
+            ],
+        );
+        assert_eq!(events.len(), 2);
+        let Event::Batch(outer) = events[0].clone() else {
+            panic!("expected a batch: {events:?}");
+        };
+        assert_eq!(outer.kind(), Kind::LabeledResponse);
+        assert_eq!(outer.label(), Some("abc"));
+        let Item::Batch(inner) = &outer.items[0] else {
+            panic!("expected a nested batch: {outer:?}");
+        };
+        assert_eq!(inner.kind(), Kind::ChatHistory { target: "#c" });
+        assert_eq!(outer.into_messages().len(), 2);
+        assert!(matches!(events[1], Event::Message(_)));
+    }
+
+    #[test]
+    fn never_ended() {
+        let mut assembler = Assembler::with_limits(2, 2, TIMEOUT);
+        let events = push_all(
+            &mut assembler,
+            &[
+                ":irc.host BATCH +a chathistory #c",
+                "@batch=a :nick!u@h PRIVMSG #c :kept",
+                ":irc.host BATCH +b chathistory #c",
+                ":irc.host BATCH +c chathistory #c",
+                "@batch=a :nick!u@h PRIVMSG #c :a was dropped",
+                "@batch=b :nick!u@h PRIVMSG #c :one",
+                "@batch=b :nick!u@h PRIVMSG #c :two",
+                "@batch=b :nick!u@h PRIVMSG #c :too many",
+            ],
+        );
+        let texts = events
+            .iter()
+            .map(|e| match e {
+                Event::Message(m) => m.command.clone(),
+                Event::Batch(b) => panic!("unexpected batch: {b:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            [
+                Command::PRIVMSG("#c".to_owned(), "a was dropped".to_owned()),
+                Command::PRIVMSG("#c".to_owned(), "too many".to_owned()),
+            ]
+        );
+        assert_eq!(assembler.pending(), 1);
+        assert_eq!(assembler.dropped(), 2);
+
+        let mut assembler = Assembler::with_limits(MAX_OPEN, MAX_ITEMS, Duration::ZERO);
+        let events = push_all(
+            &mut assembler,
+            &[
+                ":irc.host BATCH +outer netsplit a b",
+                "@batch=outer :irc.host BATCH +inner chathistory #c",
+                "@batch=inner :nick!u@h PRIVMSG #c :timed out",
+            ],
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(assembler.pending(), 0);
+        assert_eq!(assembler.dropped(), 2);
+    }
+}
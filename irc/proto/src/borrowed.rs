@@ -0,0 +1,365 @@
+//! Borrowed messages that point into the line they were parsed from.
+//!
+//! [`MessageRef::parse`] accepts the same messages as [`crate::parse::message`], without
+//! allocating a string for every tag, source and parameter. Use [`MessageRef::into_owned`]
+//! for a [`Message`] you can keep.
+
+use std::borrow::Cow;
+
+use crate::{parse::Error, Command, Message, Source, Tag, User};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagRef<'a> {
+    pub key: &'a str,
+    /// unescaped value; only allocates when the value has escapes.
+    pub value: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserRef<'a> {
+    pub nickname: &'a str,
+    pub username: Option<&'a str>,
+    pub hostname: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceRef<'a> {
+    Server(&'a str),
+    User(UserRef<'a>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRef<'a> {
+    /// command as sent, e.g. `privmsg` or `001`.
+    pub name: &'a str,
+    pub params: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageRef<'a> {
+    pub tags: Vec<TagRef<'a>>,
+    pub source: Option<SourceRef<'a>>,
+    pub command: CommandRef<'a>,
+}
+
+impl TagRef<'_> {
+    pub fn into_owned(self) -> Tag {
+        Tag {
+            key: self.key.to_owned(),
+            value: self.value.map(Cow::into_owned),
+        }
+    }
+}
+
+impl UserRef<'_> {
+    pub fn into_owned(self) -> User {
+        User {
+            nickname: self.nickname.to_owned(),
+            username: self.username.map(str::to_owned),
+            hostname: self.hostname.map(str::to_owned),
+        }
+    }
+}
+
+impl SourceRef<'_> {
+    pub fn into_owned(self) -> Source {
+        match self {
+            SourceRef::Server(server) => Source::Server(server.to_owned()),
+            SourceRef::User(user) => Source::User(user.into_owned()),
+        }
+    }
+}
+
+impl CommandRef<'_> {
+    pub fn into_owned(self) -> Command {
+        Command::new(
+            self.name,
+            self.params.into_iter().map(str::to_owned).collect(),
+        )
+    }
+}
+
+impl<'a> MessageRef<'a> {
+    /// Parse a UTF-8 line, e.g. a frame split from a `BytesMut` buffer.
+    pub fn from_bytes(input: &'a [u8]) -> Result<Self, Error> {
+        let input = std::str::from_utf8(input).map_err(|e| Error::Parse {
+            input: String::from_utf8_lossy(input).into_owned(),
+            nom: e.to_string(),
+        })?;
+        Self::parse(input)
+    }
+
+    /// Parse a single line; a trailing line ending is optional.
+    pub fn parse(input: &'a str) -> Result<Self, Error> {
+        let error = |why: &str| Error::Parse {
+            input: input.to_owned(),
+            nom: why.to_owned(),
+        };
+        let mut rest = input.trim_end_matches(['\r', '\n']);
+
+        let mut tags = vec![];
+        if let Some(after) = rest.strip_prefix('@') {
+            let (raw, after) = after
+                .split_once(' ')
+                .ok_or_else(|| error("missing command"))?;
+            // escaped values never contain a semicolon; `\;` is a stray escape, then a new tag.
+            for tag in raw.split(';') {
+                tags.push(parse_tag(tag).ok_or_else(|| error("invalid tag"))?);
+            }
+            rest = after.trim_start_matches(' ');
+        }
+
+        let mut source = None;
+        if let Some(after) = rest.strip_prefix(':') {
+            let (raw, after) = after
+                .split_once(' ')
+                .ok_or_else(|| error("missing command"))?;
+            if raw.is_empty() {
+                return Err(error("empty source"));
+            }
+            source = Some(match parse_user(raw) {
+                Some(user) => SourceRef::User(user),
+                None => SourceRef::Server(raw),
+            });
+            rest = after.trim_start_matches(' ');
+        }
+
+        let end = rest.find(' ').unwrap_or(rest.len());
+        let name = &rest[..end];
+        let is_word = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphabetic());
+        let is_numeric = name.len() == 3 && name.bytes().all(|b| b.is_ascii_digit());
+        if !is_word && !is_numeric {
+            return Err(error("invalid command"));
+        }
+        rest = &rest[end..];
+
+        let mut params = vec![];
+        loop {
+            let param = rest.trim_start_matches(' ');
+            if param.is_empty() {
+                break;
+            }
+            if param.len() == rest.len() {
+                // parameters must be separated by a space.
+                return Err(error("invalid parameter"));
+            }
+            if let Some(trailing) = param.strip_prefix(':') {
+                if trailing.contains(['\0', '\r', '\n']) {
+                    return Err(error("invalid trailing parameter"));
+                }
+                params.push(trailing);
+                break;
+            }
+            let end = param.find(' ').unwrap_or(param.len());
+            let (middle, after) = param.split_at(end);
+            if middle.contains(['\0', '\r', '\n']) {
+                return Err(error("invalid parameter"));
+            }
+            params.push(middle);
+            rest = after;
+        }
+
+        Ok(Self {
+            tags,
+            source,
+            command: CommandRef { name, params },
+        })
+    }
+
+    pub fn into_owned(self) -> Message {
+        Message {
+            tags: self.tags.into_iter().map(TagRef::into_owned).collect(),
+            source: self.source.map(SourceRef::into_owned),
+            command: self.command.into_owned(),
+            raw: None,
+        }
+    }
+}
+
+/// `[ '+' ] [ <vendor> '/' ] <key name> [ '=' <escaped value> ]`
+fn parse_tag(tag: &str) -> Option<TagRef<'_>> {
+    let (key, value) = match tag.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (tag, None),
+    };
+    let name = key.strip_prefix('+').unwrap_or(key);
+    let name = match name.split_once('/') {
+        Some((vendor, name)) if !vendor.is_empty() => name,
+        Some(_) => return None,
+        None => name,
+    };
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+        return None;
+    }
+    let value = match value {
+        Some(v) if v.contains('\0') => return None,
+        Some(v) if v.contains('\\') => Some(Cow::Owned(unescape(v))),
+        Some(v) => Some(Cow::Borrowed(v)),
+        None => None,
+    };
+    // an empty value is the same as no value.
+    let value = value.filter(|v| !v.is_empty());
+    Some(TagRef { key, value })
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            // drops the escape of anything else, including a trailing escape.
+            Some(c) => out.push(c),
+            None => (),
+        }
+    }
+    out
+}
+
+fn is_special(b: u8) -> bool {
+    b"-[]\\`_^{|}*/@".contains(&b)
+}
+
+/// `<nickname> [ '!' <user> ] [ '@' <host> ]`, covering all of source.
+fn parse_user(source: &str) -> Option<UserRef<'_>> {
+    let bytes = source.as_bytes();
+    let strict = bytes
+        .iter()
+        .position(|&b| !(b.is_ascii_alphanumeric() || is_special(b)))
+        .unwrap_or(bytes.len());
+    // nicknames like `foo:matrix.org`, used by bridges, when followed by '!'.
+    let expanded = bytes
+        .iter()
+        .position(|&b| !(b.is_ascii_alphanumeric() || is_special(b) || b == b':' || b == b'.'))
+        .filter(|&end| bytes[end] == b'!')
+        .filter(|&end| source[..end].contains(':') && source[..end].contains('.'));
+    let nick_end = expanded.unwrap_or(strict);
+    if nick_end == 0 {
+        return None;
+    }
+    let (nickname, mut rest) = source.split_at(nick_end);
+
+    let mut username = None;
+    if let Some(after) = rest.strip_prefix('!') {
+        let end = after
+            .find(['\0', '\r', '\n', ' ', '@'])
+            .unwrap_or(after.len());
+        if end > 0 {
+            username = Some(&after[..end]);
+            rest = &after[end..];
+        }
+    }
+    let mut hostname = None;
+    if let Some(after) = rest.strip_prefix('@') {
+        if !after.is_empty() {
+            hostname = Some(after);
+            rest = "";
+        }
+    }
+    rest.is_empty().then_some(UserRef {
+        nickname,
+        username,
+        hostname,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Lines both parsers must agree on.
+    const LINES: &[&str] = &[
+        ":irc.example.com CAP LS * :multi-prefix extended-join sasl\r\n",
+        "@id=234AB :dan!d@localhost PRIVMSG #chan :Hey what's up! \r\n",
+        "CAP REQ :sasl\r\n",
+        "@tag=as\\\\\\:\\sdf\\z\\ UNKNOWN\r\n",
+        "@+1.1.1.1/wi2-asef-1=as\\\\\\:\\sdf\\z\\ UNKNOWN\r\n",
+        ":test!test@5555:5555:0:55:5555:5555:5555:5555 396 test user/test :is now your visible host\r\n",
+        ":atw.hu.quakenet.org 001 test :Welcome to the QuakeNet IRC Network, test\r\n",
+        "@time=2023-07-20T21:19:11.000Z :chat!test@user/test/bot/chat PRIVMSG ##chat :\\_o< quack!\r\n",
+        "@batch=JQlhpjWY7SYaBPQtXAfUQh;msgid=UGnor4DBoafs6ge0UgsHF7-aVdnYMbjbdTf9eEHQmPKWA;time=2024-11-07T12:04:28.361Z :foo!~foo@F3FF3610.5A633F24.29800D3F.IP JOIN #pixelcove * :foo\r\r\n",
+        "@batch=AhaatzFmHPzct87cyiyxk4;time=2025-01-15T22:54:02.123Z;msgid=pgON6bxXjG7unoKIYwC3aV-KPRYjZhmCa3ZReibvMIrgw :atarians.dejatoons.net MODE #test +nt \r\n",
+        ":dan@id/network!d@remote.host PRIVMSG #c ::)\r\n",
+        ":foo:matrix.org!foo@matrix.org PRIVMSG #c :hi\r\n",
+        ":foobar/server!~foobar@555.555.555.555.abc.efg.com NOTICE me :x\r\n",
+        ":1111:FFFF::1 PONG 1111:FFFF::1 :token\r\n",
+        ":nick MODE nick :+i\r\n",
+        ":nick!@host PING x\r\n",
+        "PING\r\n",
+        "PRIVMSG   #a    b   :  c d  \r\n",
+        "privmsg #a :lower case\r\n",
+        "@a;b=;c=\\ 005 me A=1 B :are supported\r\n",
+        "@e=q\\o\\;f=x :n!u@h PRIVMSG #c :stray escape before a semicolon\r\n",
+    ];
+
+    #[test]
+    fn matches_nom() {
+        for line in LINES {
+            let borrowed = MessageRef::parse(line).map(MessageRef::into_owned);
+            match parse::message(line) {
+                Ok(owned) => assert_eq!(borrowed.unwrap(), owned, "parsing {line:?}"),
+                // we accept empty tag values, which nom does not.
+                Err(_) => assert!(line.contains("b=;"), "parsing {line:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn borrowed() {
+        let line = "@a=b\\sc;d :nick!user@host PRIVMSG #chan :hello world";
+        let message = MessageRef::parse(line).unwrap();
+        assert_eq!(
+            message.tags,
+            vec![
+                TagRef {
+                    key: "a",
+                    value: Some(Cow::Owned("b c".to_owned()))
+                },
+                TagRef {
+                    key: "d",
+                    value: None
+                },
+            ]
+        );
+        assert_eq!(
+            message.source,
+            Some(SourceRef::User(UserRef {
+                nickname: "nick",
+                username: Some("user"),
+                hostname: Some("host"),
+            }))
+        );
+        assert_eq!(message.command.name, "PRIVMSG");
+        assert_eq!(message.command.params, vec!["#chan", "hello world"]);
+        assert!(message.tags[1].value.is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let lines = [
+            "",
+            "@tag",
+            ": PRIVMSG",
+            "@=x PING",
+            "12 PING",
+            "PRIV.MSG #a",
+            "PING #a\0b",
+        ];
+        for line in lines {
+            assert!(MessageRef::parse(line).is_err(), "parsing {line:?}");
+            assert!(
+                parse::message(&format!("{line}\r\n")).is_err(),
+                "nom parsing {line:?}"
+            );
+        }
+        assert!(MessageRef::from_bytes(b"PRIVMSG #a :\xff").is_err());
+    }
+}
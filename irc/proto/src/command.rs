@@ -61,6 +61,8 @@ pub enum Command {
     /// None
     INFO,
     /// <target> [<modestring> [<mode arguments>...]]
+    ///
+    /// Parsed mode arguments are always `Some`, and empty when there are none.
     MODE(String, Option<String>, Option<Vec<String>>),
 
     /* Sending Messages */
@@ -128,10 +130,7 @@ impl Command {
         use Command::*;
 
         if let Ok(num) = tag.parse::<u16>() {
-            return match self::Numeric::try_from(num) {
-                Ok(numeric) => Numeric(numeric, parameters),
-                Err(_) => Unknown(num.to_string(), parameters),
-            };
+            return Numeric(self::Numeric::new(num), parameters);
         }
 
         let tag = tag.to_uppercase();
@@ -161,6 +160,11 @@ impl Command {
             "AUTHENTICATE" if len > 0 => AUTHENTICATE(req!()),
             "PASS" if len > 0 => PASS(req!()),
             "NICK" if len > 0 => NICK(req!()),
+            // `USER <username> 0 * <realname>`, as we write it.
+            "USER" if len > 3 => {
+                let username = req!();
+                USER(username, params.nth(2).unwrap())
+            }
             "USER" if len > 1 => USER(req!(), req!()),
             "PING" if len > 0 => PING(req!()),
             "PONG" if len > 0 => PONG(req!(), opt!()),
@@ -186,7 +190,19 @@ impl Command {
             "MODE" if len > 0 => MODE(req!(), opt!(), Some(params.collect())),
             "PRIVMSG" if len > 1 => PRIVMSG(req!(), req!()),
             "NOTICE" if len > 1 => NOTICE(req!(), req!()),
-            "WHO" if len > 0 => WHO(req!(), opt!(), opt!()),
+            "WHO" if len > 0 => {
+                let mask = req!();
+                // WHOX: `%<fields>[,<token>]`.
+                match opt!() {
+                    Some(whox) if whox.starts_with('%') => match whox[1..].split_once(',') {
+                        Some((fields, token)) => {
+                            WHO(mask, Some(fields.to_owned()), Some(token.to_owned()))
+                        }
+                        None => WHO(mask, Some(whox[1..].to_owned()), None),
+                    },
+                    fields => WHO(mask, fields, None),
+                }
+            }
             "WHOIS" => {
                 let a = req!();
                 match opt!() {
@@ -337,27 +353,120 @@ impl Command {
             MONITOR(_, _) => "MONITOR".to_string(),
             TAGMSG(_) => "TAGMSG".to_string(),
             USERIP(_) => "USERIP".to_string(),
-            Numeric(numeric, _) => format!("{:03}", *numeric as u16),
+            Numeric(numeric, _) => format!("{:03}", numeric.code()),
             Unknown(tag, _) => tag.clone(),
             Raw(_) => "".to_string(),
         }
     }
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u16)]
-pub enum Numeric {
+macro_rules! numerics {
+    ($($name:ident = $code:literal,)*) => {
+        /// A reply numeric; numerics are equal when their codes are.
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, Eq)]
+        pub enum Numeric {
+            $($name,)*
+            /// A numeric we have no name for; only for codes without a name, see [`Numeric::new`].
+            ///
+            /// Matching on `Other` never sees a named code, but comparing does:
+            /// `Numeric::Other(1) == Numeric::RPL_WELCOME`.
+            Other(u16),
+        }
+
+        impl Numeric {
+            /// The named numeric for code, or `Other(code)`.
+            pub fn new(code: u16) -> Self {
+                Self::try_from(code).unwrap_or(Numeric::Other(code))
+            }
+
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(Numeric::$name => $code,)*
+                    Numeric::Other(code) => *code,
+                }
+            }
+
+            /// Name of the numeric, e.g. `RPL_WELCOME`.
+            pub fn name(&self) -> Option<&'static str> {
+                match self {
+                    $(Numeric::$name => Some(stringify!($name)),)*
+                    Numeric::Other(_) => None,
+                }
+            }
+        }
+
+        /// Named numerics only; use [`Numeric::new`] for any code.
+        impl TryFrom<u16> for Numeric {
+            type Error = ();
+
+            fn try_from(code: u16) -> Result<Self, Self::Error> {
+                match code {
+                    $($code => Ok(Numeric::$name),)*
+                    _ => Err(()),
+                }
+            }
+        }
+
+        impl From<Numeric> for u16 {
+            fn from(numeric: Numeric) -> Self {
+                numeric.code()
+            }
+        }
+
+        impl PartialEq for Numeric {
+            fn eq(&self, other: &Self) -> bool {
+                self.code() == other.code()
+            }
+        }
+
+        impl std::hash::Hash for Numeric {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.code().hash(state);
+            }
+        }
+    };
+}
+
+// Reference: https://modern.ircdocs.horse/#numerics
+// Names for codes servers disagree on follow modern.ircdocs, then the most common server.
+numerics! {
+    /* Connection registration */
     RPL_WELCOME = 1,
     RPL_YOURHOST = 2,
     RPL_CREATED = 3,
     RPL_MYINFO = 4,
     RPL_ISUPPORT = 5,
+    RPL_SNOMASK = 8,
     RPL_BOUNCE = 10,
+    RPL_YOURID = 42,
+
+    /* Server queries */
+    RPL_TRACELINK = 200,
+    RPL_TRACECONNECTING = 201,
+    RPL_TRACEHANDSHAKE = 202,
+    RPL_TRACEUNKNOWN = 203,
+    RPL_TRACEOPERATOR = 204,
+    RPL_TRACEUSER = 205,
+    RPL_TRACESERVER = 206,
+    RPL_TRACESERVICE = 207,
+    RPL_TRACENEWTYPE = 208,
+    RPL_TRACECLASS = 209,
+    RPL_STATSLINKINFO = 211,
     RPL_STATSCOMMANDS = 212,
+    RPL_STATSCLINE = 213,
+    RPL_STATSILINE = 215,
+    RPL_STATSKLINE = 216,
+    RPL_STATSYLINE = 218,
     RPL_ENDOFSTATS = 219,
-    RPL_STATSUPTIME = 242,
     RPL_UMODEIS = 221,
+    RPL_SERVLIST = 234,
+    RPL_SERVLISTEND = 235,
+    RPL_STATSLLINE = 241,
+    RPL_STATSUPTIME = 242,
+    RPL_STATSOLINE = 243,
+    RPL_STATSHLINE = 244,
+    RPL_STATSCONN = 250,
     RPL_LUSERCLIENT = 251,
     RPL_LUSEROP = 252,
     RPL_LUSERUNKNOWN = 253,
@@ -367,22 +476,27 @@ pub enum Numeric {
     RPL_ADMINLOC1 = 257,
     RPL_ADMINLOC2 = 258,
     RPL_ADMINEMAIL = 259,
+    RPL_TRACELOG = 261,
+    RPL_TRACEEND = 262,
     RPL_TRYAGAIN = 263,
     RPL_LOCALUSERS = 265,
     RPL_GLOBALUSERS = 266,
     RPL_WHOISCERTFP = 276,
+
+    /* Command replies */
     RPL_NONE = 300,
     RPL_AWAY = 301,
     RPL_USERHOST = 302,
+    RPL_ISON = 303,
     RPL_UNAWAY = 305,
     RPL_NOWAWAY = 306,
-    RPL_WHOREPLY = 352,
-    RPL_ENDOFWHO = 315,
     RPL_WHOISREGNICK = 307,
+    RPL_WHOISHELPOP = 310,
     RPL_WHOISUSER = 311,
     RPL_WHOISSERVER = 312,
     RPL_WHOISOPERATOR = 313,
     RPL_WHOWASUSER = 314,
+    RPL_ENDOFWHO = 315,
     RPL_WHOISIDLE = 317,
     RPL_ENDOFWHOIS = 318,
     RPL_WHOISCHANNELS = 319,
@@ -391,38 +505,51 @@ pub enum Numeric {
     RPL_LIST = 322,
     RPL_LISTEND = 323,
     RPL_CHANNELMODEIS = 324,
+    RPL_CHANNEL_URL = 328,
     RPL_CREATIONTIME = 329,
     RPL_WHOISACCOUNT = 330,
     RPL_NOTOPIC = 331,
     RPL_TOPIC = 332,
     RPL_TOPICWHOTIME = 333,
+    RPL_WHOISBOT = 335,
     RPL_INVITELIST = 336,
     RPL_ENDOFINVITELIST = 337,
     RPL_WHOISACTUALLY = 338,
+    RPL_USERIP = 340,
     RPL_INVITING = 341,
     RPL_INVEXLIST = 346,
     RPL_ENDOFINVEXLIST = 347,
     RPL_EXCEPTLIST = 348,
     RPL_ENDOFEXCEPTLIST = 349,
     RPL_VERSION = 351,
+    RPL_WHOREPLY = 352,
     RPL_NAMREPLY = 353,
+    // WHOX
     RPL_WHOSPCRPL = 354,
-    RPL_ENDOFNAMES = 366,
     RPL_LINKS = 364,
     RPL_ENDOFLINKS = 365,
+    RPL_ENDOFNAMES = 366,
     RPL_BANLIST = 367,
     RPL_ENDOFBANLIST = 368,
     RPL_ENDOFWHOWAS = 369,
     RPL_INFO = 371,
+    RPL_MOTD = 372,
     RPL_ENDOFINFO = 374,
     RPL_MOTDSTART = 375,
-    RPL_MOTD = 372,
     RPL_ENDOFMOTD = 376,
     RPL_WHOISHOST = 378,
     RPL_WHOISMODES = 379,
     RPL_YOUREOPER = 381,
     RPL_REHASHING = 382,
+    RPL_YOURESERVICE = 383,
     RPL_TIME = 391,
+    RPL_USERSSTART = 392,
+    RPL_USERS = 393,
+    RPL_ENDOFUSERS = 394,
+    RPL_NOUSERS = 395,
+    RPL_VISIBLEHOST = 396,
+
+    /* Errors */
     ERR_UNKNOWNERROR = 400,
     ERR_NOSUCHNICK = 401,
     ERR_NOSUCHSERVER = 402,
@@ -430,24 +557,41 @@ pub enum Numeric {
     ERR_CANNOTSENDTOCHAN = 404,
     ERR_TOOMANYCHANNELS = 405,
     ERR_WASNOSUCHNICK = 406,
+    ERR_TOOMANYTARGETS = 407,
+    ERR_NOSUCHSERVICE = 408,
     ERR_NOORIGIN = 409,
     ERR_NORECIPIENT = 411,
     ERR_NOTEXTTOSEND = 412,
+    ERR_NOTOPLEVEL = 413,
+    ERR_WILDTOPLEVEL = 414,
+    ERR_BADMASK = 415,
+    ERR_TOOMANYMATCHES = 416,
     ERR_INPUTTOOLONG = 417,
     ERR_UNKNOWNCOMMAND = 421,
     ERR_NOMOTD = 422,
+    ERR_NOADMININFO = 423,
+    ERR_FILEERROR = 424,
     ERR_NONICKNAMEGIVEN = 431,
     ERR_ERRONEUSNICKNAME = 432,
     ERR_NICKNAMEINUSE = 433,
     ERR_NICKCOLLISION = 436,
+    ERR_UNAVAILRESOURCE = 437,
     ERR_USERNOTINCHANNEL = 441,
     ERR_NOTONCHANNEL = 442,
     ERR_USERONCHANNEL = 443,
+    ERR_NOLOGIN = 444,
+    ERR_SUMMONDISABLED = 445,
+    ERR_USERSDISABLED = 446,
+    ERR_NONICKCHANGE = 447,
     ERR_NOTREGISTERED = 451,
     ERR_NEEDMOREPARAMS = 461,
     ERR_ALREADYREGISTERED = 462,
+    ERR_NOPERMFORHOST = 463,
     ERR_PASSWDMISMATCH = 464,
     ERR_YOUREBANNEDCREEP = 465,
+    ERR_YOUWILLBEBANNED = 466,
+    ERR_KEYSET = 467,
+    ERR_LINKCHANNEL = 470,
     ERR_CHANNELISFULL = 471,
     ERR_UNKNOWNMODE = 472,
     ERR_INVITEONLYCHAN = 473,
@@ -455,14 +599,20 @@ pub enum Numeric {
     ERR_BADCHANNELKEY = 475,
     ERR_BADCHANMASK = 476,
     ERR_NOCHANMODES = 477,
+    ERR_BANLISTFULL = 478,
     ERR_NOPRIVILEGES = 481,
     ERR_CHANOPRIVSNEEDED = 482,
     ERR_CANTKILLSERVER = 483,
+    ERR_RESTRICTED = 484,
+    ERR_UNIQOPPRIVSNEEDED = 485,
+    ERR_SECUREONLYCHAN = 489,
     ERR_NOOPERHOST = 491,
     ERR_UMODEUNKNOWNFLAG = 501,
     ERR_USERSDONTMATCH = 502,
     ERR_HELPNOTFOUND = 524,
     ERR_INVALIDKEY = 525,
+
+    /* Extensions */
     RPL_STARTTLS = 670,
     RPL_WHOISSECURE = 671,
     ERR_STARTTLS = 691,
@@ -470,12 +620,23 @@ pub enum Numeric {
     RPL_HELPSTART = 704,
     RPL_HELPTXT = 705,
     RPL_ENDOFHELP = 706,
+    RPL_KNOCK = 710,
+    RPL_KNOCKDLVR = 711,
+    ERR_TOOMANYKNOCK = 712,
+    ERR_CHANOPEN = 713,
+    ERR_KNOCKONCHAN = 714,
+    ERR_TARGUMODEG = 716,
+    RPL_TARGNOTIFY = 717,
+    RPL_UMODEGMSG = 718,
     ERR_NOPRIVS = 723,
+    RPL_QUIETLIST = 728,
+    RPL_ENDOFQUIETLIST = 729,
     RPL_MONONLINE = 730,
     RPL_MONOFFLINE = 731,
     RPL_MONLIST = 732,
     RPL_ENDOFMONLIST = 733,
     ERR_MONLISTFULL = 734,
+    ERR_MLOCKRESTRICTED = 742,
     RPL_LOGGEDIN = 900,
     RPL_LOGGEDOUT = 901,
     ERR_NICKLOCKED = 902,
@@ -485,153 +646,81 @@ pub enum Numeric {
     ERR_SASLABORTED = 906,
     ERR_SASLALREADY = 907,
     RPL_SASLMECHS = 908,
+    ERR_CANNOTDOCOMMAND = 972,
+    ERR_CANNOTCHANGECHANMODE = 974,
 }
 
-impl TryFrom<u16> for Numeric {
-    type Error = ();
-
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        use Numeric::*;
-
-        Ok(match value {
-            1 => RPL_WELCOME,
-            2 => RPL_YOURHOST,
-            3 => RPL_CREATED,
-            4 => RPL_MYINFO,
-            5 => RPL_ISUPPORT,
-            10 => RPL_BOUNCE,
-            212 => RPL_STATSCOMMANDS,
-            219 => RPL_ENDOFSTATS,
-            242 => RPL_STATSUPTIME,
-            221 => RPL_UMODEIS,
-            251 => RPL_LUSERCLIENT,
-            252 => RPL_LUSEROP,
-            253 => RPL_LUSERUNKNOWN,
-            254 => RPL_LUSERCHANNELS,
-            255 => RPL_LUSERME,
-            256 => RPL_ADMINME,
-            257 => RPL_ADMINLOC1,
-            258 => RPL_ADMINLOC2,
-            259 => RPL_ADMINEMAIL,
-            263 => RPL_TRYAGAIN,
-            265 => RPL_LOCALUSERS,
-            266 => RPL_GLOBALUSERS,
-            276 => RPL_WHOISCERTFP,
-            300 => RPL_NONE,
-            301 => RPL_AWAY,
-            302 => RPL_USERHOST,
-            305 => RPL_UNAWAY,
-            306 => RPL_NOWAWAY,
-            352 => RPL_WHOREPLY,
-            315 => RPL_ENDOFWHO,
-            307 => RPL_WHOISREGNICK,
-            311 => RPL_WHOISUSER,
-            312 => RPL_WHOISSERVER,
-            313 => RPL_WHOISOPERATOR,
-            314 => RPL_WHOWASUSER,
-            317 => RPL_WHOISIDLE,
-            318 => RPL_ENDOFWHOIS,
-            319 => RPL_WHOISCHANNELS,
-            320 => RPL_WHOISSPECIAL,
-            321 => RPL_LISTSTART,
-            322 => RPL_LIST,
-            323 => RPL_LISTEND,
-            324 => RPL_CHANNELMODEIS,
-            329 => RPL_CREATIONTIME,
-            330 => RPL_WHOISACCOUNT,
-            331 => RPL_NOTOPIC,
-            332 => RPL_TOPIC,
-            333 => RPL_TOPICWHOTIME,
-            336 => RPL_INVITELIST,
-            337 => RPL_ENDOFINVITELIST,
-            338 => RPL_WHOISACTUALLY,
-            341 => RPL_INVITING,
-            346 => RPL_INVEXLIST,
-            347 => RPL_ENDOFINVEXLIST,
-            348 => RPL_EXCEPTLIST,
-            349 => RPL_ENDOFEXCEPTLIST,
-            351 => RPL_VERSION,
-            353 => RPL_NAMREPLY,
-            354 => RPL_WHOSPCRPL,
-            366 => RPL_ENDOFNAMES,
-            364 => RPL_LINKS,
-            365 => RPL_ENDOFLINKS,
-            367 => RPL_BANLIST,
-            368 => RPL_ENDOFBANLIST,
-            369 => RPL_ENDOFWHOWAS,
-            371 => RPL_INFO,
-            374 => RPL_ENDOFINFO,
-            375 => RPL_MOTDSTART,
-            372 => RPL_MOTD,
-            376 => RPL_ENDOFMOTD,
-            378 => RPL_WHOISHOST,
-            379 => RPL_WHOISMODES,
-            381 => RPL_YOUREOPER,
-            382 => RPL_REHASHING,
-            391 => RPL_TIME,
-            400 => ERR_UNKNOWNERROR,
-            401 => ERR_NOSUCHNICK,
-            402 => ERR_NOSUCHSERVER,
-            403 => ERR_NOSUCHCHANNEL,
-            404 => ERR_CANNOTSENDTOCHAN,
-            405 => ERR_TOOMANYCHANNELS,
-            406 => ERR_WASNOSUCHNICK,
-            409 => ERR_NOORIGIN,
-            411 => ERR_NORECIPIENT,
-            412 => ERR_NOTEXTTOSEND,
-            417 => ERR_INPUTTOOLONG,
-            421 => ERR_UNKNOWNCOMMAND,
-            422 => ERR_NOMOTD,
-            431 => ERR_NONICKNAMEGIVEN,
-            432 => ERR_ERRONEUSNICKNAME,
-            433 => ERR_NICKNAMEINUSE,
-            436 => ERR_NICKCOLLISION,
-            441 => ERR_USERNOTINCHANNEL,
-            442 => ERR_NOTONCHANNEL,
-            443 => ERR_USERONCHANNEL,
-            451 => ERR_NOTREGISTERED,
-            461 => ERR_NEEDMOREPARAMS,
-            462 => ERR_ALREADYREGISTERED,
-            464 => ERR_PASSWDMISMATCH,
-            465 => ERR_YOUREBANNEDCREEP,
-            471 => ERR_CHANNELISFULL,
-            472 => ERR_UNKNOWNMODE,
-            473 => ERR_INVITEONLYCHAN,
-            474 => ERR_BANNEDFROMCHAN,
-            475 => ERR_BADCHANNELKEY,
-            476 => ERR_BADCHANMASK,
-            477 => ERR_NOCHANMODES,
-            481 => ERR_NOPRIVILEGES,
-            482 => ERR_CHANOPRIVSNEEDED,
-            483 => ERR_CANTKILLSERVER,
-            491 => ERR_NOOPERHOST,
-            501 => ERR_UMODEUNKNOWNFLAG,
-            502 => ERR_USERSDONTMATCH,
-            524 => ERR_HELPNOTFOUND,
-            525 => ERR_INVALIDKEY,
-            670 => RPL_STARTTLS,
-            671 => RPL_WHOISSECURE,
-            691 => ERR_STARTTLS,
-            696 => ERR_INVALIDMODEPARAM,
-            704 => RPL_HELPSTART,
-            705 => RPL_HELPTXT,
-            706 => RPL_ENDOFHELP,
-            723 => ERR_NOPRIVS,
-            730 => RPL_MONONLINE,
-            731 => RPL_MONOFFLINE,
-            732 => RPL_MONLIST,
-            733 => RPL_ENDOFMONLIST,
-            734 => ERR_MONLISTFULL,
-            900 => RPL_LOGGEDIN,
-            901 => RPL_LOGGEDOUT,
-            902 => ERR_NICKLOCKED,
-            903 => RPL_SASLSUCCESS,
-            904 => ERR_SASLFAIL,
-            905 => ERR_SASLTOOLONG,
-            906 => ERR_SASLABORTED,
-            907 => ERR_SASLALREADY,
-            908 => RPL_SASLMECHS,
-            _ => return Err(()),
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format, parse};
+
+    #[test]
+    fn numerics() {
+        assert_eq!(Numeric::new(354), Numeric::RPL_WHOSPCRPL);
+        assert_eq!(Numeric::try_from(354), Ok(Numeric::RPL_WHOSPCRPL));
+        assert_eq!(Numeric::try_from(999), Err(()));
+        assert_eq!(u16::from(Numeric::RPL_WELCOME), 1);
+        assert_eq!(Numeric::RPL_MONONLINE.code(), 730);
+        assert_eq!(
+            Numeric::ERR_CANNOTSENDTOCHAN.name(),
+            Some("ERR_CANNOTSENDTOCHAN")
+        );
+        assert_eq!(Numeric::new(999), Numeric::Other(999));
+        assert_eq!(Numeric::Other(999).name(), None);
+        assert!(matches!(Numeric::new(1), Numeric::RPL_WELCOME));
+        assert_eq!(Numeric::Other(1), Numeric::RPL_WELCOME);
+
+        let message = parse::message(":irc.host 099 moose :something new\r\n").unwrap();
+        assert_eq!(
+            message.command,
+            Command::Numeric(
+                Numeric::Other(99),
+                vec!["moose".to_owned(), "something new".to_owned()]
+            )
+        );
+        assert_eq!(message.command.command(), "099");
+    }
+
+    #[test]
+    fn mode() {
+        assert_eq!(
+            parse::message("MODE #moose +nt\r\n").unwrap().command,
+            Command::MODE("#moose".to_owned(), Some("+nt".to_owned()), Some(vec![]))
+        );
+        assert_eq!(
+            parse::message("MODE MrMoose\r\n").unwrap().command,
+            Command::MODE("MrMoose".to_owned(), None, Some(vec![]))
+        );
+    }
+
+    #[test]
+    fn user_and_who() {
+        let user = Command::USER("moose".to_owned(), "Moose Bot".to_owned());
+        let line = format::message(user.clone().into());
+        assert_eq!(line, "USER moose 0 * :Moose Bot\r\n");
+        assert_eq!(parse::message(&line).unwrap().command, user);
+        assert_eq!(
+            parse::message("USER moose moose\r\n").unwrap().command,
+            Command::USER("moose".to_owned(), "moose".to_owned())
+        );
+
+        let who = Command::WHO(
+            "#moose".to_owned(),
+            Some("tna".to_owned()),
+            Some("42".to_owned()),
+        );
+        let line = format::message(who.clone().into());
+        assert_eq!(line, "WHO #moose %tna,42\r\n");
+        assert_eq!(parse::message(&line).unwrap().command, who);
+        assert_eq!(
+            parse::message("WHO #moose %n\r\n").unwrap().command,
+            Command::WHO("#moose".to_owned(), Some("n".to_owned()), None)
+        );
+        // the RFC 1459 operator flag isn't WHOX.
+        assert_eq!(
+            parse::message("WHO #moose o\r\n").unwrap().command,
+            Command::WHO("#moose".to_owned(), Some("o".to_owned()), None)
+        );
     }
 }
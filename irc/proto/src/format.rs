@@ -2,7 +2,7 @@ use std::fmt::Write;
 
 use itertools::Itertools;
 
-use crate::{Command, Message, Tag};
+use crate::{Command, Message, Source, Tag};
 
 /// Most IRC servers limit messages to 512 bytes in length, including the trailing CR-LF characters.
 pub const BYTE_LIMIT: usize = 512;
@@ -16,6 +16,10 @@ pub fn message(message: Message) -> String {
         let _ = write!(&mut output, "@{tags} ");
     }
 
+    if let Some(source) = message.source {
+        let _ = write!(&mut output, ":{} ", self::source(source));
+    }
+
     if let Command::Raw(raw) = &message.command {
         let _ = write!(&mut output, "{raw}");
     } else {
@@ -55,6 +59,22 @@ fn tag(tag: Tag) -> String {
     }
 }
 
+fn source(source: Source) -> String {
+    match source {
+        Source::Server(server) => server,
+        Source::User(user) => {
+            let mut output = user.nickname;
+            if let Some(username) = user.username {
+                let _ = write!(&mut output, "!{username}");
+            }
+            if let Some(hostname) = user.hostname {
+                let _ = write!(&mut output, "@{hostname}");
+            }
+            output
+        }
+    }
+}
+
 fn parameters(parameters: Vec<String>) -> String {
     let params_len = parameters.len();
     parameters
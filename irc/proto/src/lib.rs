@@ -1,14 +1,44 @@
+pub use self::borrowed::{CommandRef, MessageRef};
 pub use self::command::Command;
 
+pub mod batch;
+pub mod borrowed;
 pub mod command;
 pub mod format;
 pub mod parse;
+pub mod sts;
+pub mod tags;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg(feature = "serde")]
+mod serialize;
+
+#[cfg(test)]
+mod strategy;
+
+#[derive(Debug, Clone, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
     pub tags: Vec<Tag>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     pub source: Option<Source>,
     pub command: Command,
+    /// bytes the message was decoded from, if the codec keeps them.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub raw: Option<Vec<u8>>,
+}
+
+/// Messages are equal when they say the same thing, however they were received.
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.tags == other.tags && self.source == other.source && self.command == other.command
+    }
 }
 
 impl From<Command> for Message {
@@ -17,26 +47,43 @@ impl From<Command> for Message {
             tags: vec![],
             source: None,
             command,
+            raw: None,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tag {
     pub key: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     pub value: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Source {
     Server(String),
     User(User),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct User {
     pub nickname: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     pub username: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     pub hostname: Option<String>,
 }
 
@@ -45,6 +92,7 @@ pub fn command(command: &str, parameters: Vec<String>) -> Message {
         tags: vec![],
         source: None,
         command: Command::new(command, parameters),
+        raw: None,
     }
 }
 /// Reference: https://defs.ircdocs.horse/defs/chantypes
@@ -107,6 +155,16 @@ mod tests {
     // Reference: https://defs.ircdocs.horse/defs/chanmembers
     const CHANNEL_MEMBERSHIP_PREFIXES: &[char] = &['~', '&', '!', '@', '%', '+'];
 
+    #[test]
+    fn equal_ignores_raw() {
+        let message = Message::from(Command::PING("a".to_owned()));
+        let received = Message {
+            raw: Some(b"PING :a\r\n".to_vec()),
+            ..message.clone()
+        };
+        assert_eq!(message, received);
+    }
+
     #[test]
     fn is_channel_correct() {
         let chantypes = DEFAULT_CHANNEL_PREFIXES;
@@ -27,6 +27,7 @@ pub fn message(input: &str) -> Result<Message, Error> {
             tags: tags.unwrap_or_default(),
             source,
             command,
+            raw: None,
         })
         .map_err(|e| Error::Parse {
             input: input.to_string(),
@@ -41,13 +42,13 @@ fn tags(input: &str) -> IResult<&str, Vec<Tag>> {
         value('\\', tag(r"\\")),
         value('\r', tag(r"\r")),
         value('\n', tag(r"\n")),
-        // drop escape char '\'
-        preceded(char('\\'), none_of(r":s\rn ")),
+        // drop escape char '\'; a semicolon always ends the tag.
+        preceded(char('\\'), none_of(r":s\rn ;")),
     ));
     // <sequence of any escaped characters except NUL, CR, LF, semicolon (`;`) and SPACE>
     let escaped_value = map(
         terminated(
-            many1(alt((escaped_char, none_of("\0\r\n;\\ ")))),
+            many0(alt((escaped_char, none_of("\0\r\n;\\ ")))),
             // drop trailing escape char '\'
             opt(char('\\')),
         ),
@@ -64,9 +65,10 @@ fn tags(input: &str) -> IResult<&str, Vec<Tag>> {
     // <key> ['=' <escaped value>]
     let tag = map(
         tuple((key, opt(preceded(char('='), escaped_value)))),
-        |(key, value): (&str, _)| Tag {
+        |(key, value): (&str, Option<String>)| Tag {
             key: key.to_string(),
-            value,
+            // an empty value is the same as no value.
+            value: value.filter(|v| !v.is_empty()),
         },
     );
     // <tag> [';' <tag>]*
@@ -263,6 +265,7 @@ mod test {
                         Some("multi-prefix extended-join sasl".to_string()),
                         None,
                     ),
+                    raw: None,
                 },
             ),
             (
@@ -278,6 +281,7 @@ mod test {
                         hostname: Some("localhost".into()),
                     })),
                     command: Command::PRIVMSG("#chan".to_string(), "Hey what's up! ".to_string()),
+                    raw: None,
                 },
             ),
             (
@@ -286,6 +290,7 @@ mod test {
                     tags: vec![],
                     source: None,
                     command: Command::CAP(Some("REQ".to_string()), "sasl".to_string(), None, None),
+                    raw: None,
                 },
             ),
             (
@@ -297,6 +302,7 @@ mod test {
                     }],
                     source: None,
                     command: Command::Unknown("UNKNOWN".to_string(), vec![]),
+                    raw: None,
                 },
             ),
             (
@@ -308,6 +314,43 @@ mod test {
                     }],
                     source: None,
                     command: Command::Unknown("UNKNOWN".to_string(), vec![]),
+                    raw: None,
+                },
+            ),
+            (
+                "@e=q\\o\\;f=x PING x\r\n",
+                Message {
+                    tags: vec![
+                        Tag {
+                            key: "e".to_string(),
+                            value: Some("qo".to_string()),
+                        },
+                        Tag {
+                            key: "f".to_string(),
+                            value: Some("x".to_string()),
+                        },
+                    ],
+                    source: None,
+                    command: Command::PING("x".to_string()),
+                    raw: None,
+                },
+            ),
+            (
+                "@e=;f=\\ PING x\r\n",
+                Message {
+                    tags: vec![
+                        Tag {
+                            key: "e".to_string(),
+                            value: None,
+                        },
+                        Tag {
+                            key: "f".to_string(),
+                            value: None,
+                        },
+                    ],
+                    source: None,
+                    command: Command::PING("x".to_string()),
+                    raw: None,
                 },
             ),
             (
@@ -319,14 +362,15 @@ mod test {
                         username: Some("test".into()),
                         hostname: Some("5555:5555:0:55:5555:5555:5555:5555".into()),
                     })),
-                    command: Command::Unknown(
-                        "396".to_string(),
+                    command: Command::Numeric(
+                        RPL_VISIBLEHOST,
                         vec![
                             "test".to_string(),
                             "user/test".to_string(),
                             "is now your visible host".to_string(),
                         ],
                     ),
+                    raw: None,
                 },
             ),
             (
@@ -341,6 +385,7 @@ mod test {
                             "Welcome to the QuakeNet IRC Network, test".to_string(),
                         ],
                     ),
+                    raw: None,
                 },
             ),
             (
@@ -356,6 +401,7 @@ mod test {
                         hostname: Some("user/test/bot/chat".into()),
                     })),
                     command: Command::PRIVMSG("##chat".to_string(), "\\_o< quack!".to_string()),
+                    raw: None,
                 },
             ),
             // Extra \r sent by digitalirc
@@ -384,6 +430,7 @@ mod test {
                         hostname: Some("F3FF3610.5A633F24.29800D3F.IP".into()),
                     })),
                     command: Command::JOIN("#pixelcove".to_string(), Some("*".to_string())),
+                    raw: None,
                 },
             ),
             // Space between message and crlf sent by DejaToons
@@ -408,6 +455,7 @@ mod test {
                     ],
                     source: Some(Source::Server("atarians.dejatoons.net".to_string())),
                     command: Command::MODE("#test".to_string(), Some("+nt".to_string()), Some(vec![])),
+                    raw: None,
                 },
             ),
         ];
@@ -0,0 +1,141 @@
+//! Serde support, with the `serde` feature.
+//!
+//! Commands are written as their name and parameters, the same as they are sent:
+//!
+//! ```json
+//! {"command": "PRIVMSG", "params": ["#moose", "hello"]}
+//! {"command": "001", "code": 1, "name": "RPL_WELCOME", "params": ["moose", "Welcome"]}
+//! {"command": "099", "code": 99, "params": ["moose", "Unnamed"]}
+//! {"raw": "PRIVMSG #moose :hello"}
+//! ```
+//!
+//! `code` and `name` are only informative; commands are read back from `command` and `params`.
+
+use ::serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{command::Numeric, Command};
+
+impl Serialize for Numeric {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Numeric", 2)?;
+        state.serialize_field("code", &self.code())?;
+        match self.name() {
+            Some(name) => state.serialize_field("name", name)?,
+            None => state.skip_field("name")?,
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Numeric {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Code {
+            code: u16,
+        }
+
+        let Code { code } = Code::deserialize(deserializer)?;
+        Ok(Numeric::new(code))
+    }
+}
+
+impl Serialize for Command {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Command::Raw(raw) = self {
+            let mut state = serializer.serialize_struct("Command", 1)?;
+            state.serialize_field("raw", raw)?;
+            return state.end();
+        }
+        let numeric = match self {
+            Command::Numeric(numeric, _) => Some(numeric),
+            _ => None,
+        };
+        let len = if numeric.is_some() { 4 } else { 2 };
+        let mut state = serializer.serialize_struct("Command", len)?;
+        state.serialize_field("command", &self.command())?;
+        if let Some(numeric) = numeric {
+            state.serialize_field("code", &numeric.code())?;
+            match numeric.name() {
+                Some(name) => state.serialize_field("name", name)?,
+                None => state.skip_field("name")?,
+            }
+        }
+        state.serialize_field("params", &self.clone().parameters())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Command {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            command: Option<String>,
+            #[serde(default)]
+            params: Vec<String>,
+            raw: Option<String>,
+        }
+
+        match Fields::deserialize(deserializer)? {
+            Fields { raw: Some(raw), .. } => Ok(Command::Raw(raw)),
+            Fields {
+                command: Some(command),
+                params,
+                ..
+            } => Ok(Command::new(&command, params)),
+            _ => Err(de::Error::missing_field("command")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{command::Numeric, parse, Command, Message};
+
+    #[test]
+    fn json() {
+        let message = parse::message(
+            "@time=2024-11-07T12:04:28.361Z;bot :dan!~d@localhost PRIVMSG #moose :hello world\r\n",
+        )
+        .unwrap();
+        let value = json!({
+            "tags": [
+                {"key": "time", "value": "2024-11-07T12:04:28.361Z"},
+                {"key": "bot"},
+            ],
+            "source": {"user": {"nickname": "dan", "username": "~d", "hostname": "localhost"}},
+            "command": {"command": "PRIVMSG", "params": ["#moose", "hello world"]},
+        });
+        assert_eq!(serde_json::to_value(&message).unwrap(), value);
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), message);
+
+        let message = parse::message(":irc.host 001 moose :Welcome\r\n").unwrap();
+        let value = json!({
+            "source": {"server": "irc.host"},
+            "command": {"command": "001", "code": 1, "name": "RPL_WELCOME", "params": ["moose", "Welcome"]},
+        });
+        assert_eq!(serde_json::to_value(&message).unwrap(), value);
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), message);
+
+        let raw = Message::from(Command::Raw("PING x".to_owned()));
+        let value = json!({"command": {"raw": "PING x"}});
+        assert_eq!(serde_json::to_value(&raw).unwrap(), value);
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), raw);
+    }
+
+    #[test]
+    fn numerics() {
+        let numeric = Numeric::ERR_CANNOTSENDTOCHAN;
+        let value = json!({"code": 404, "name": "ERR_CANNOTSENDTOCHAN"});
+        assert_eq!(serde_json::to_value(numeric).unwrap(), value);
+        assert_eq!(serde_json::from_value::<Numeric>(value).unwrap(), numeric);
+        let value = json!({"code": 999});
+        assert_eq!(serde_json::to_value(Numeric::Other(999)).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<Numeric>(value).unwrap(),
+            Numeric::Other(999)
+        );
+        assert!(serde_json::from_value::<Command>(json!({"params": []})).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! proptest strategies for messages `format` can write and `parse` can read back.
+
+use proptest::{collection::vec, option, prelude::*, sample::select};
+
+use crate::{command::Numeric, Command, Message, Source, Tag, User};
+
+/// Parameter that isn't last; no spaces and no leading colon.
+pub fn middle() -> impl Strategy<Value = String> {
+    "[^\0\r\n :][^\0\r\n ]{0,15}"
+}
+
+/// Last parameter; anything but line endings and NUL.
+pub fn trailing() -> impl Strategy<Value = String> {
+    "[^\0\r\n]{0,32}"
+}
+
+/// Parameters for commands that take any number of them.
+pub fn params() -> impl Strategy<Value = Vec<String>> {
+    (vec(middle(), 0..6), option::of(trailing()))
+        .prop_map(|(middles, trailing)| middles.into_iter().chain(trailing).collect())
+}
+
+pub fn tag() -> impl Strategy<Value = Tag> {
+    let key = (
+        option::of(Just("+")),
+        option::of("[a-z0-9.]{1,12}/"),
+        "[A-Za-z0-9-]{1,16}",
+    )
+        .prop_map(|(client, vendor, name)| {
+            format!(
+                "{}{}{name}",
+                client.unwrap_or_default(),
+                vendor.unwrap_or_default()
+            )
+        });
+    // empty values are the same as no value.
+    (key, option::of("[^\0]{1,24}")).prop_map(|(key, value)| Tag { key, value })
+}
+
+pub fn source() -> impl Strategy<Value = Source> {
+    let nickname = "[A-Za-z\\[\\]\\\\`_^{|}][A-Za-z0-9\\[\\]\\\\`_^{|}-]{0,15}";
+    let user = (
+        nickname,
+        option::of((
+            "~?[a-z0-9]{1,10}",
+            "[a-z0-9:/-]{1,12}(\\.[a-z0-9-]{1,12}){0,3}",
+        )),
+    )
+        .prop_map(|(nickname, host)| {
+            let (username, hostname) = host.unzip();
+            User {
+                nickname,
+                username,
+                hostname,
+            }
+        });
+    prop_oneof![
+        // servers have a dot; otherwise they look like a nickname.
+        "[a-z0-9*-]{1,12}(\\.[a-z0-9-]{1,12}){1,3}".prop_map(Source::Server),
+        user.prop_map(Source::User),
+    ]
+}
+
+pub fn numeric() -> impl Strategy<Value = Numeric> {
+    (0u16..1000).prop_map(Numeric::new)
+}
+
+/// Every command but [`Command::Raw`], which is written as is.
+pub fn command() -> impl Strategy<Value = Command> {
+    use Command::*;
+
+    let m = middle;
+    let t = trailing;
+    let opt_m = || option::of(middle());
+    let opt_t = || option::of(trailing());
+    // one optional parameter, then the last parameter, which only exists after the first.
+    let pair = || option::of((middle(), option::of(trailing())));
+
+    prop_oneof![
+        prop_oneof![
+            (option::of(m()), m(), option::of((m(), opt_t()))).prop_map(|(a, b, rest)| {
+                match (a, rest) {
+                    (Some(a), Some((c, d))) => CAP(Some(a), b, Some(c), d),
+                    (Some(a), None) => CAP(Some(a), b, None, None),
+                    (None, _) => CAP(None, b, None, None),
+                }
+            }),
+            t().prop_map(AUTHENTICATE),
+            t().prop_map(PASS),
+            t().prop_map(NICK),
+            (m(), t()).prop_map(|(a, b)| USER(a, b)),
+            t().prop_map(PING),
+            (m(), opt_t()).prop_map(|(a, b)| PONG(a, b)),
+            (m(), t()).prop_map(|(a, b)| OPER(a, b)),
+            opt_t().prop_map(QUIT),
+            t().prop_map(ERROR),
+            (m(), opt_t()).prop_map(|(a, b)| JOIN(a, b)),
+            (m(), opt_t()).prop_map(|(a, b)| PART(a, b)),
+            (m(), opt_t()).prop_map(|(a, b)| TOPIC(a, b)),
+            t().prop_map(NAMES),
+            pair().prop_map(|p| {
+                let (a, b) = p.unzip();
+                LIST(a, b.flatten())
+            }),
+            (m(), t()).prop_map(|(a, b)| INVITE(a, b)),
+            (m(), m(), opt_t()).prop_map(|(a, b, c)| KICK(a, b, c)),
+        ],
+        prop_oneof![
+            opt_t().prop_map(MOTD),
+            opt_t().prop_map(VERSION),
+            opt_t().prop_map(ADMIN),
+            (m(), option::of((m(), opt_t()))).prop_map(|(a, rest)| {
+                let (b, c) = rest.unzip();
+                CONNECT(a, b, c.flatten())
+            }),
+            Just(LUSERS),
+            opt_t().prop_map(TIME),
+            (m(), opt_t()).prop_map(|(a, b)| STATS(a, b)),
+            opt_t().prop_map(HELP),
+            Just(INFO),
+            // arguments are always read back, if only as an empty list.
+            (m(), option::of((m(), vec(m(), 0..4)))).prop_map(|(a, rest)| match rest {
+                Some((b, c)) => MODE(a, Some(b), Some(c)),
+                None => MODE(a, None, Some(vec![])),
+            }),
+            (m(), t()).prop_map(|(a, b)| PRIVMSG(a, b)),
+            (m(), t()).prop_map(|(a, b)| NOTICE(a, b)),
+            (m(), option::of(("[a-z]{1,8}", option::of("[0-9]{1,3}")))).prop_map(|(a, fields)| {
+                let (b, c) = fields.unzip();
+                WHO(a, b, c.flatten())
+            }),
+            (opt_m(), t()).prop_map(|(a, b)| WHOIS(a, b)),
+            (m(), opt_t()).prop_map(|(a, b)| WHOWAS(a, b)),
+            (m(), t()).prop_map(|(a, b)| KILL(a, b)),
+            Just(REHASH),
+        ],
+        prop_oneof![
+            Just(RESTART),
+            (m(), t()).prop_map(|(a, b)| SQUIT(a, b)),
+            opt_t().prop_map(AWAY),
+            Just(LINKS),
+            params().prop_map(USERHOST),
+            t().prop_map(WALLOPS),
+            t().prop_map(ACCOUNT),
+            (m(), params()).prop_map(|(a, b)| BATCH(a, b)),
+            (m(), params()).prop_map(|(a, b)| CHATHISTORY(a, b)),
+            (m(), t()).prop_map(|(a, b)| CHGHOST(a, b)),
+            (m(), m(), t()).prop_map(|(a, b, c)| CNOTICE(a, b, c)),
+            (m(), m(), t()).prop_map(|(a, b, c)| CPRIVMSG(a, b, c)),
+            (m(), opt_t()).prop_map(|(a, b)| KNOCK(a, b)),
+            (m(), opt_t()).prop_map(|(a, b)| MARKREAD(a, b)),
+            (m(), opt_t()).prop_map(|(a, b)| MONITOR(a, b)),
+            t().prop_map(TAGMSG),
+            t().prop_map(USERIP),
+            (numeric(), params()).prop_map(|(n, p)| Numeric(n, p)),
+            (
+                select(&["SETNAME", "REDACT", "FAIL", "WARN", "NOTE", "XYZZY"][..])
+                    .prop_map(str::to_owned),
+                params()
+            )
+                .prop_map(|(a, b)| Unknown(a, b)),
+        ],
+    ]
+}
+
+pub fn message() -> impl Strategy<Value = Message> {
+    (vec(tag(), 0..4), option::of(source()), command()).prop_map(|(tags, source, command)| {
+        Message {
+            tags,
+            source,
+            command,
+            raw: None,
+        }
+    })
+}
+
+/// Lines shaped like IRC messages, which may or may not be valid.
+pub fn line() -> impl Strategy<Value = String> {
+    "(@[-+a-z0-9/.=;\\\\]{1,20} )?(:[a-z0-9.!@~:/_-]{1,24} )?[A-Za-z0-9]{1,8}( [^\0\r\n :][^\0\r\n ]{0,8}){0,4}( :[^\0\r\n]{0,16})?\r\n"
+}
+
+mod tests {
+    use super::*;
+    use crate::{format, parse, MessageRef};
+
+    proptest! {
+        #[test]
+        fn round_trip(message in message()) {
+            let line = format::message(message.clone());
+            prop_assert_eq!(parse::message(&line).unwrap(), message.clone(), "{:?}", line);
+            prop_assert_eq!(MessageRef::parse(&line).unwrap().into_owned(), message, "{:?}", line);
+        }
+
+        #[test]
+        fn reformat(line in line()) {
+            // formatting whatever we parse gives a line that parses the same.
+            if let Ok(message) = parse::message(&line) {
+                let formatted = format::message(message.clone());
+                prop_assert_eq!(parse::message(&formatted).unwrap(), message, "{:?}", formatted);
+            }
+        }
+
+        #[test]
+        fn parsers_agree(line in line()) {
+            let borrowed = MessageRef::parse(&line).map(MessageRef::into_owned);
+            match parse::message(&line) {
+                Ok(message) => prop_assert_eq!(borrowed.unwrap(), message),
+                Err(_) => prop_assert!(borrowed.is_err(), "{:?}", line),
+            }
+        }
+    }
+
+    /// Lines from real servers; see `corpus/`.
+    #[test]
+    fn corpus() {
+        let corpus = [
+            include_str!("../corpus/ergo.txt"),
+            include_str!("../corpus/inspircd.txt"),
+            include_str!("../corpus/solanum.txt"),
+            include_str!("../corpus/unrealircd.txt"),
+        ];
+        for line in corpus.iter().flat_map(|c| c.lines()) {
+            let line = format!("{line}\r\n");
+            let message = parse::message(&line).unwrap();
+            assert_eq!(
+                MessageRef::parse(&line).unwrap().into_owned(),
+                message,
+                "parsing {line:?}"
+            );
+            let formatted = format::message(message.clone());
+            assert_eq!(
+                parse::message(&formatted).unwrap(),
+                message,
+                "{formatted:?}"
+            );
+        }
+    }
+}
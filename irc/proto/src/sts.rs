@@ -0,0 +1,88 @@
+//! Strict transport security policies.
+//!
+//! Reference: https://ircv3.net/specs/extensions/sts
+
+use std::time::Duration;
+
+/// Name of the capability that carries the policy.
+pub const CAP: &str = "sts";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// TLS port to reconnect to; only meaningful on plaintext connections.
+    pub port: Option<u16>,
+    /// How long to keep using TLS; only meaningful on secure connections.
+    pub duration: Option<Duration>,
+    pub preload: bool,
+}
+
+impl Policy {
+    /// Parse a policy like `port=6697,duration=300`; a key with an invalid value invalidates it.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut policy = Policy::default();
+        for pair in value.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "port" => policy.port = Some(value.parse().ok().filter(|p| *p != 0)?),
+                "duration" => policy.duration = Some(Duration::from_secs(value.parse().ok()?)),
+                "preload" => policy.preload = true,
+                // unknown keys are for future use.
+                _ => (),
+            }
+        }
+        Some(policy)
+    }
+
+    /// Policy in a `CAP LS` list of capabilities, if offered.
+    pub fn offered(caps: &str) -> Option<Self> {
+        caps.split_ascii_whitespace()
+            .find_map(|c| match c.split_once('=') {
+                Some((CAP, value)) => Some(value),
+                None if c == CAP => Some(""),
+                _ => None,
+            })
+            .and_then(Self::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let tests = [
+            (
+                "port=6697,duration=300",
+                Some(Policy {
+                    port: Some(6697),
+                    duration: Some(Duration::from_secs(300)),
+                    preload: false,
+                }),
+            ),
+            (
+                "duration=0,preload,future=x",
+                Some(Policy {
+                    port: None,
+                    duration: Some(Duration::ZERO),
+                    preload: true,
+                }),
+            ),
+            ("", Some(Policy::default())),
+            ("port=tls", None),
+            ("port=0", None),
+            ("duration=-1", None),
+        ];
+        for (test, expected) in tests {
+            assert_eq!(Policy::parse(test), expected, "parsing {test}");
+        }
+        assert_eq!(
+            Policy::offered("batch sasl=PLAIN sts=port=6697 server-time"),
+            Some(Policy {
+                port: Some(6697),
+                ..Policy::default()
+            })
+        );
+        assert_eq!(Policy::offered("batch sasl=PLAIN"), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Typed access to well known IRCv3 message tags.
+//!
+//! Reference: https://ircv3.net/registry#tags
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Message, Tag};
+
+/// https://ircv3.net/specs/extensions/server-time
+pub const TIME: &str = "time";
+/// https://ircv3.net/specs/extensions/message-ids
+pub const MSGID: &str = "msgid";
+/// https://ircv3.net/specs/extensions/account-tag
+pub const ACCOUNT: &str = "account";
+/// https://ircv3.net/specs/extensions/batch
+pub const BATCH: &str = "batch";
+/// https://ircv3.net/specs/extensions/labeled-response
+pub const LABEL: &str = "label";
+/// https://ircv3.net/specs/client-tags/reply
+pub const REPLY: &str = "+draft/reply";
+
+impl Message {
+    /// Value of the tag named key; a tag without a value is `Some("")`.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|t| t.key == key)
+            .map(|t| t.value.as_deref().unwrap_or_default())
+    }
+
+    /// Set the tag named key, replacing it if it already exists.
+    pub fn set_tag(&mut self, key: &str, value: Option<String>) {
+        match self.tags.iter_mut().find(|t| t.key == key) {
+            Some(tag) => tag.value = value,
+            None => self.tags.push(Tag {
+                key: key.to_owned(),
+                value,
+            }),
+        }
+    }
+
+    pub fn with_tag(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.set_tag(key, Some(value.into()));
+        self
+    }
+
+    /// Value of a tag that is meaningless when empty.
+    fn nonempty_tag(&self, key: &str) -> Option<&str> {
+        self.tag(key).filter(|v| !v.is_empty())
+    }
+
+    /// When the server received the message.
+    pub fn time(&self) -> Option<SystemTime> {
+        self.nonempty_tag(TIME).and_then(parse_time)
+    }
+
+    pub fn msgid(&self) -> Option<&str> {
+        self.nonempty_tag(MSGID)
+    }
+
+    /// Services account of the sender; `*` means they are not logged in.
+    pub fn account(&self) -> Option<&str> {
+        self.nonempty_tag(ACCOUNT).filter(|a| *a != "*")
+    }
+
+    /// Reference of the batch the message belongs to.
+    pub fn batch(&self) -> Option<&str> {
+        self.nonempty_tag(BATCH)
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.nonempty_tag(LABEL)
+    }
+
+    /// msgid of the message this one replies to.
+    pub fn reply(&self) -> Option<&str> {
+        self.nonempty_tag(REPLY)
+    }
+}
+
+/// Days since the unix epoch for a proleptic gregorian date.
+///
+/// Reference: https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parse a server-time timestamp, `YYYY-MM-DDThh:mm:ss[.sss]Z`.
+pub fn parse_time(time: &str) -> Option<SystemTime> {
+    let time = time.strip_suffix('Z')?;
+    let (date, clock) = time.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse::<i64>().ok()?;
+    let month = date.next()?.parse::<u32>().ok()?;
+    let day = date.next()?.parse::<u32>().ok()?;
+    let (clock, fraction) = match clock.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (clock, None),
+    };
+    let mut clock = clock.splitn(3, ':');
+    let hour = clock.next()?.parse::<u64>().ok()?;
+    let minute = clock.next()?.parse::<u64>().ok()?;
+    let second = clock.next()?.parse::<u64>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let nanos = match fraction {
+        Some(f) if f.is_empty() || f.len() > 9 || !f.bytes().all(|b| b.is_ascii_digit()) => {
+            return None
+        }
+        Some(f) => f.parse::<u32>().ok()? * 10u32.pow(9 - f.len() as u32),
+        None => 0,
+    };
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+/// Format a time as a server-time timestamp with millisecond precision.
+pub fn format_time(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let clock = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        clock / 3600,
+        clock % 3600 / 60,
+        clock % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn accessors() {
+        let message = parse::message(
+            "@time=2011-10-19T16:40:51.620Z;msgid=abc;account=*;batch=b1;label=l;+draft/reply=xyz;empty :nick!u@h PRIVMSG #c :hi\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            message.time(),
+            Some(UNIX_EPOCH + Duration::from_millis(1319042451620))
+        );
+        assert_eq!(message.msgid(), Some("abc"));
+        assert_eq!(message.account(), None);
+        assert_eq!(message.batch(), Some("b1"));
+        assert_eq!(message.label(), Some("l"));
+        assert_eq!(message.reply(), Some("xyz"));
+        assert_eq!(message.tag("empty"), Some(""));
+        assert_eq!(message.tag("missing"), None);
+
+        let message = message.with_tag(ACCOUNT, "moose").with_tag(REPLY, "new");
+        assert_eq!(message.account(), Some("moose"));
+        assert_eq!(message.reply(), Some("new"));
+        assert_eq!(message.tags.len(), 7);
+    }
+
+    #[test]
+    fn time() {
+        let tests = [
+            ("1970-01-01T00:00:00Z", Some(0)),
+            ("2011-10-19T16:40:51.620Z", Some(1319042451620)),
+            ("2000-02-29T23:59:59.5Z", Some(951868799500)),
+            ("2011-10-19T16:40:51.620", None),
+            ("2011-13-19T16:40:51Z", None),
+            ("2011-10-19 16:40:51Z", None),
+            ("1969-12-31T23:59:59Z", None),
+            ("2011-10-19T16:40:51.Z", None),
+        ];
+        for (test, expected) in tests {
+            assert_eq!(
+                parse_time(test),
+                expected.map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+                "parsing {test}"
+            );
+        }
+        for ms in [0, 951868799500, 1319042451620, 4102444800000] {
+            let time = UNIX_EPOCH + Duration::from_millis(ms);
+            assert_eq!(parse_time(&format_time(time)), Some(time));
+        }
+    }
+}
@@ -1,14 +1,222 @@
-use std::io;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use proto::{format, parse, Message};
+use proto::{format, parse, Command, Message, MessageRef};
 use tokio_util::bytes::{Buf, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
+use crate::encoding::{Charset, Encoding};
+
 pub type ParseResult<T = Message, E = parse::Error> = std::result::Result<T, E>;
 
-pub struct Codec;
+/// Longest message, without tags, including the line ending.
+///
+/// Reference: https://modern.ircdocs.horse/#messages
+pub const MAX_MESSAGE: usize = 512;
+/// Most bytes tags may take, including the leading `@` and trailing space.
+///
+/// Reference: https://ircv3.net/specs/extensions/message-tags#size-limit
+pub const MAX_TAGS: usize = 8191;
+
+/// Number of oversized lines a codec has discarded, shared with whoever built it.
+#[derive(Debug, Clone, Default)]
+pub struct Discarded(Arc<AtomicUsize>);
+
+impl Discarded {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Codec {
+    encoding: Encoding,
+    /// charsets of channels that differ from the server, by lowercase name.
+    channels: HashMap<String, Charset>,
+    keep_raw: bool,
+    max_line_length: usize,
+    recover: bool,
+    /// skipping the rest of an oversized line.
+    discarding: bool,
+    discarded: Discarded,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Builder::default().build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Builder {
+    encoding: Encoding,
+    channels: HashMap<String, Charset>,
+    keep_raw: bool,
+    max_line_length: usize,
+    recover: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::default(),
+            channels: HashMap::new(),
+            keep_raw: false,
+            max_line_length: MAX_MESSAGE + MAX_TAGS,
+            recover: false,
+        }
+    }
+}
+
+impl Builder {
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Decode and encode messages involving channel in charset.
+    pub fn channel_encoding(mut self, channel: &str, charset: Charset) -> Self {
+        self.channels.insert(channel.to_lowercase(), charset);
+        self
+    }
+
+    /// Keep the bytes of every decoded line in [`Message::raw`].
+    pub fn keep_raw(mut self, keep_raw: bool) -> Self {
+        self.keep_raw = keep_raw;
+        self
+    }
+
+    /// Longest line we accept, including tags and the line ending.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Discard lines that are too long, instead of failing with [`Error::LineTooLong`].
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    pub fn build(self) -> Codec {
+        Codec {
+            encoding: self.encoding,
+            channels: self.channels,
+            keep_raw: self.keep_raw,
+            max_line_length: self.max_line_length,
+            recover: self.recover,
+            discarding: false,
+            discarded: Discarded::default(),
+        }
+    }
+}
+
+impl Codec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Counter of lines discarded for being too long; only counts when recovering.
+    pub fn discarded(&self) -> Discarded {
+        self.discarded.clone()
+    }
+
+    /// Charset of the first channel in the message's parameters we have one for.
+    fn channel_charset(&self, message: &MessageRef) -> Option<Charset> {
+        message
+            .command
+            .params
+            .iter()
+            .find_map(|p| self.channels.get(&p.to_lowercase()).copied())
+    }
+
+    /// Charset of the channel an outgoing command is for.
+    fn command_charset(&self, command: &Command) -> Option<Charset> {
+        use Command::*;
+        let target = match command {
+            PRIVMSG(target, _) | NOTICE(target, _) | TAGMSG(target) => target,
+            JOIN(target, _) | PART(target, _) | TOPIC(target, _) | KICK(target, _, _) => target,
+            KNOCK(target, _) | MODE(target, _, _) | NAMES(target) => target,
+            INVITE(_, target) | CPRIVMSG(_, target, _) | CNOTICE(_, target, _) => target,
+            Numeric(_, params) | Unknown(_, params) => {
+                return params
+                    .iter()
+                    .find_map(|p| self.channels.get(&p.to_lowercase()).copied())
+            }
+            _ => return None,
+        };
+        self.channels.get(&target.to_lowercase()).copied()
+    }
+
+    fn decode_line<'a>(&self, line: &'a [u8]) -> ParseResult<Cow<'a, str>> {
+        self.encoding
+            .decode(line)
+            .ok_or_else(|| parse::Error::Parse {
+                input: String::from_utf8_lossy(line).into_owned(),
+                nom: "invalid utf-8".to_owned(),
+            })
+    }
+
+    /// Parse a line in the charset of its channel, and pass the message to f.
+    ///
+    /// The message borrows line, unless it has to be converted to UTF-8.
+    pub fn parse_line<T>(
+        &self,
+        line: &[u8],
+        f: impl FnOnce(MessageRef<'_>) -> T,
+    ) -> ParseResult<T> {
+        // every charset decodes ascii the same.
+        if line.is_ascii() || self.channels.is_empty() {
+            return Ok(f(MessageRef::parse(&self.decode_line(line)?)?));
+        }
+        // channel names are ascii, which latin-1 always decodes.
+        let latin1 = Charset::Latin1.decode(line);
+        let message = MessageRef::parse(&latin1)?;
+        let decoded = match self.channel_charset(&message) {
+            Some(Charset::Latin1) => return Ok(f(message)),
+            Some(charset) => charset.decode(line),
+            None => self.decode_line(line)?,
+        };
+        Ok(f(MessageRef::parse(&decoded)?))
+    }
+
+    /// Take the next line from src and pass it to f, like [`Decoder::decode`] without copying it.
+    pub fn decode_ref<T>(
+        &mut self,
+        src: &mut BytesMut,
+        f: impl FnOnce(MessageRef<'_>) -> T,
+    ) -> Result<Option<ParseResult<T>>, Error> {
+        Ok(self.next_line(src)?.map(|line| self.parse_line(&line, f)))
+    }
+
+    /// Drop an oversized line, or the part of it we have.
+    fn discard(&mut self, src: &mut BytesMut, len: usize) -> Result<(), Error> {
+        if !self.recover {
+            return Err(Error::LineTooLong);
+        }
+        if !self.discarding {
+            self.discarded.add();
+        }
+        src.advance(len);
+        Ok(())
+    }
+}
 
-const BUF_MAX: usize = 2usize.pow(16);
 const LINE_END: [u8; 2] = [b'\r', b'\n'];
 
 fn find_eom(buf: &[u8]) -> Option<usize> {
@@ -24,34 +232,57 @@ impl Decoder for Codec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // remove any leading line endings.
-        let Some(start) = find_start(src) else {
-            if src.len() > BUF_MAX {
-                return Err(Error::LineTooLong);
-            } else {
-                return Ok(None);
-            }
+        let Some(line) = self.next_line(src)? else {
+            return Ok(None);
         };
-        src.advance(start);
+        let message = self.parse_line(&line, |m| m.into_owned());
+        Ok(Some(message.map(|mut message| {
+            if self.keep_raw {
+                message.raw = Some(line.to_vec());
+            }
+            message
+        })))
+    }
+}
+
+impl Codec {
+    /// Split the next complete line off src, without its line ending.
+    fn next_line(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+        loop {
+            // remove any leading line endings.
+            // a line ending also ends the line we were discarding.
+            let Some(start) = find_start(src) else {
+                self.discarding &= src.is_empty();
+                src.clear();
+                return Ok(None);
+            };
+            self.discarding &= start == 0;
+            src.advance(start);
 
-        // get message.
-        let Some(eom) = find_eom(src) else {
-            if src.len() > BUF_MAX {
-                return Err(Error::LineTooLong);
-            } else {
+            // get message.
+            let Some(eom) = find_eom(src) else {
+                if self.discarding || src.len() >= self.max_line_length {
+                    // resynchronize at the next line ending.
+                    self.discard(src, src.len())?;
+                    self.discarding = true;
+                }
                 return Ok(None);
+            };
+            if self.discarding || eom + LINE_END.len() > self.max_line_length {
+                self.discard(src, eom)?;
+                self.discarding = false;
+                continue;
             }
-        };
-        let mut bytes = Vec::from(src.split_to(eom));
-        bytes.extend(LINE_END);
+            let line = src.split_to(eom);
 
-        // remove any trailing line endings.
-        match find_start(src) {
-            Some(trailing) => src.advance(trailing),
-            None => src.clear(),
-        };
+            // remove any trailing line endings.
+            match find_start(src) {
+                Some(trailing) => src.advance(trailing),
+                None => src.clear(),
+            };
 
-        Ok(Some(parse::message_bytes(bytes)))
+            return Ok(Some(line));
+        }
     }
 }
 
@@ -59,9 +290,14 @@ impl Encoder<Message> for Codec {
     type Error = Error;
 
     fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let channel = match self.channels.is_empty() {
+            true => None,
+            false => self.command_charset(&message.command),
+        };
+        let charset = channel.unwrap_or_else(|| self.encoding.charset());
         let encoded = format::message(message);
 
-        dst.extend(encoded.into_bytes());
+        dst.extend_from_slice(&charset.encode(&encoded));
 
         Ok(())
     }
@@ -81,16 +317,20 @@ mod test {
 
     use futures::{SinkExt, StreamExt};
     use proto::Command;
-    use tokio_util::codec::{FramedRead, FramedWrite};
+    use tokio_util::{
+        bytes::BytesMut,
+        codec::{FramedRead, FramedWrite},
+    };
 
-    use super::Codec;
+    use super::{Codec, Error};
+    use crate::{Charset, Encoding};
 
     #[tokio::test]
     async fn test_decode() {
         let message = Cursor::new(
             b"\r\n\r\n:test!test@test.example.com PRIVMSG you :Hello, World!\r\nPING :xyz\r\nPONG\n\n",
         );
-        let decoder = Codec {};
+        let decoder = Codec::new();
         let mut reader = FramedRead::new(message, decoder);
         let mut full_cnt = 0;
         while let Some(frame) = reader.next().await {
@@ -108,7 +348,7 @@ mod test {
     async fn test_encode() {
         let mut buf = Cursor::new(vec![]);
         {
-            let encoder = Codec {};
+            let encoder = Codec::new();
 
             let mut writer = FramedWrite::new(&mut buf, encoder);
             let hello_world =
@@ -125,4 +365,141 @@ mod test {
             b"PRIVMSG #test :Hello, world!\r\nQUIT :Bye, world.\r\n"
         );
     }
+
+    #[tokio::test]
+    async fn test_encodings() {
+        let message = Cursor::new(
+            b":a!a@a PRIVMSG #utf8 :caf\xc3\xa9\r\n:a!a@a PRIVMSG #Latin :caf\xe9\r\n:a!a@a PRIVMSG #utf8 :caf\xe9\r\n".to_vec(),
+        );
+        let decoder = Codec::builder()
+            .encoding(Encoding::Strict)
+            .channel_encoding("#latin", Charset::Latin1)
+            .keep_raw(true)
+            .build();
+        let mut reader = FramedRead::new(message, decoder);
+        let mut texts = vec![];
+        while let Some(frame) = reader.next().await {
+            match frame.expect("no codec errors") {
+                Ok(message) => {
+                    assert!(message
+                        .raw
+                        .is_some_and(|r| r.ends_with(b"caf\xc3\xa9") || r.ends_with(b"caf\xe9")));
+                    match message.command {
+                        Command::PRIVMSG(_, text) => texts.push(Some(text)),
+                        command => panic!("unexpected {command:?}"),
+                    }
+                }
+                Err(_) => texts.push(None),
+            }
+        }
+        assert_eq!(
+            texts,
+            vec![Some("café".to_owned()), Some("café".to_owned()), None]
+        );
+
+        let mut buf = Cursor::new(vec![]);
+        {
+            let encoder = Codec::builder()
+                .encoding(Encoding::Legacy(Charset::Windows1252))
+                .channel_encoding("#utf8", Charset::Utf8)
+                .build();
+            let mut writer = FramedWrite::new(&mut buf, encoder);
+            for target in ["#cp1252", "#UTF8"] {
+                writer
+                    .send(Command::PRIVMSG(target.to_owned(), "5€".to_owned()).into())
+                    .await
+                    .unwrap();
+            }
+        }
+        assert_eq!(
+            buf.into_inner(),
+            b"PRIVMSG #cp1252 5\x80\r\nPRIVMSG #UTF8 5\xe2\x82\xac\r\n"
+        );
+    }
+
+    #[test]
+    fn test_decode_ref() {
+        let mut codec = Codec::builder()
+            .channel_encoding("#latin", Charset::Latin1)
+            .build();
+        let mut src = BytesMut::from(&b"PING :a\r\n:a!a@a PRIVMSG #latin :caf\xe9\r\nPING"[..]);
+        let mut commands = vec![];
+        while let Some(command) = codec
+            .decode_ref(&mut src, |m| {
+                (m.command.name.to_owned(), m.command.params.concat())
+            })
+            .unwrap()
+        {
+            commands.push(command.unwrap());
+        }
+        assert_eq!(
+            commands,
+            [
+                ("PING".to_owned(), "a".to_owned()),
+                ("PRIVMSG".to_owned(), "#latincafé".to_owned()),
+            ]
+        );
+        assert_eq!(&src[..], b"PING");
+    }
+
+    #[tokio::test]
+    async fn test_line_length() {
+        let lines = || {
+            let mut lines = b"PING :a\r\n".to_vec();
+            lines.extend(b"PRIVMSG #c :".iter().chain([b'x'; 600].iter()));
+            lines.extend(b"\r\nPING :b\r\n");
+            Cursor::new(lines)
+        };
+
+        let decoder = Codec::builder().max_line_length(512).build();
+        let mut reader = FramedRead::new(lines(), decoder);
+        assert!(reader.next().await.unwrap().is_ok());
+        assert!(matches!(
+            reader.next().await.unwrap(),
+            Err(Error::LineTooLong)
+        ));
+
+        // small buffer reads make sure we resynchronize across partial lines.
+        let decoder = Codec::builder().max_line_length(512).recover(true).build();
+        let discarded = decoder.discarded();
+        let mut reader = FramedRead::with_capacity(lines(), decoder, 64);
+        let mut pings = vec![];
+        while let Some(frame) = reader.next().await {
+            match frame
+                .expect("no codec errors")
+                .expect("parsed message.")
+                .command
+            {
+                Command::PING(token) => pings.push(token),
+                command => panic!("unexpected {command:?}"),
+            }
+        }
+        assert_eq!(pings, vec!["a", "b"]);
+        assert_eq!(discarded.get(), 1);
+    }
+
+    #[test]
+    fn test_discard_boundary() {
+        use tokio_util::codec::Decoder;
+
+        // a read ending right before the line ending of an oversized line.
+        let mut codec = Codec::builder().max_line_length(512).recover(true).build();
+        let discarded = codec.discarded();
+        let mut src = BytesMut::from(&[b'x'; 600][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"\r\nPING :b\r\n");
+        let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(message.command, Command::PING("b".to_owned()));
+        assert_eq!(discarded.get(), 1);
+
+        // or between its CR and LF.
+        let mut src = BytesMut::from(&[b'x'; 600][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"\r");
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"\nPING :c\r\n");
+        let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(message.command, Command::PING("c".to_owned()));
+        assert_eq!(discarded.get(), 2);
+    }
 }
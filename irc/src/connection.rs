@@ -1,17 +1,29 @@
-use std::path::PathBuf;
+use std::{net::IpAddr, path::PathBuf};
 
 use futures::{Sink, SinkExt, Stream, StreamExt};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
 use tokio_util::codec;
 use tokio_util::codec::Framed;
 
+mod dial;
+mod proxy;
 mod tls;
 
+pub use self::dial::Family;
+pub use self::proxy::Proxy;
+
+/// Any stream a connection can run over.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
 pub enum Connection<Codec> {
-    Tls(Framed<TlsStream<TcpStream>, Codec>),
+    Tls(Framed<Box<TlsStream<TcpStream>>, Codec>),
     Unsecured(Framed<TcpStream, Codec>),
+    /// A stream we didn't connect ourselves, e.g. a unix socket or an in-memory pipe.
+    Custom(Framed<Box<dyn Transport>, Codec>),
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +34,12 @@ pub enum Security<'a> {
         client_cert_path: Option<&'a PathBuf>,
         client_key_path: Option<&'a PathBuf>,
     },
+    /// Connect in plaintext, then upgrade with the legacy STARTTLS command.
+    StartTls {
+        root_cert_path: Option<&'a PathBuf>,
+        client_cert_path: Option<&'a PathBuf>,
+        client_key_path: Option<&'a PathBuf>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -29,32 +47,66 @@ pub struct Config<'a> {
     pub server: &'a str,
     pub port: u16,
     pub security: Security<'a>,
+    pub proxy: Option<Proxy<'a>>,
+    /// family of the server's addresses to try first.
+    pub family: Family,
+    /// local address to connect from, e.g. for a vhost.
+    pub bind: Option<IpAddr>,
 }
 
 impl<Codec> Connection<Codec> {
     pub async fn new(config: Config<'_>, codec: Codec) -> Result<Self, Error> {
-        let tcp = TcpStream::connect((config.server, config.port)).await?;
+        let mut tcp = match &config.proxy {
+            Some(proxy) => proxy::connect(proxy, config.server, config.port).await?,
+            None => dial::connect(config.server, config.port, config.family, config.bind).await?,
+        };
         tcp.set_nodelay(true).unwrap();
 
-        if let Security::Secured {
-            root_cert_path,
-            client_cert_path,
-            client_key_path,
-        } = config.security
-        {
-            let tls = tls::connect(
-                tcp,
-                config.server,
+        let (root_cert_path, client_cert_path, client_key_path) = match config.security {
+            Security::Unsecured => return Ok(Self::Unsecured(Framed::new(tcp, codec))),
+            Security::Secured {
                 root_cert_path,
                 client_cert_path,
                 client_key_path,
-            )
-            .await?;
+            } => (root_cert_path, client_cert_path, client_key_path),
+            Security::StartTls {
+                root_cert_path,
+                client_cert_path,
+                client_key_path,
+            } => {
+                tls::starttls(&mut tcp).await?;
+                (root_cert_path, client_cert_path, client_key_path)
+            }
+        };
+        let tls = tls::connect(
+            tcp,
+            config.server,
+            root_cert_path,
+            client_cert_path,
+            client_key_path,
+        )
+        .await?;
 
-            Ok(Self::Tls(Framed::new(tls, codec)))
-        } else {
-            Ok(Self::Unsecured(Framed::new(tcp, codec)))
-        }
+        Ok(Self::Tls(Framed::new(Box::new(tls), codec)))
+    }
+
+    /// Run over stream, which is already connected and secured as needed.
+    pub fn from_stream<S: Transport + 'static>(stream: S, codec: Codec) -> Self {
+        Self::Custom(Framed::new(Box::new(stream), codec))
+    }
+
+    /// Connect to a unix socket, e.g. of a local bouncer.
+    #[cfg(unix)]
+    pub async fn unix<P: AsRef<std::path::Path>>(path: P, codec: Codec) -> Result<Self, Error> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Ok(Self::from_stream(stream, codec))
+    }
+
+    /// Whether the connection is encrypted, including after STARTTLS.
+    ///
+    /// We can't tell for custom streams, so they never are.
+    pub fn is_secure(&self) -> bool {
+        matches!(self, Connection::Tls(_))
     }
 
     pub async fn shutdown(self) -> Result<(), Error> {
@@ -65,6 +117,9 @@ impl<Codec> Connection<Codec> {
             Connection::Unsecured(framed) => {
                 framed.into_inner().shutdown().await?;
             }
+            Connection::Custom(framed) => {
+                framed.into_inner().shutdown().await?;
+            }
         }
         Ok(())
     }
@@ -76,6 +131,8 @@ pub enum Error {
     Tls(#[from] tls::Error),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("proxy error: {0}")]
+    Proxy(#[from] proxy::Error),
 }
 
 macro_rules! delegate {
@@ -83,6 +140,7 @@ macro_rules! delegate {
         match $e {
             $crate::connection::Connection::Tls(framed) => framed.$($t)*,
             $crate::connection::Connection::Unsecured(framed) => framed.$($t)*,
+            $crate::connection::Connection::Custom(framed) => framed.$($t)*,
         }
     };
 }
@@ -132,3 +190,37 @@ where
         delegate!(self.get_mut(), poll_close_unpin(cx))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use futures::{SinkExt, StreamExt};
+    use proto::Command;
+    use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::Connection;
+    use crate::Codec;
+
+    #[tokio::test]
+    async fn test_from_stream() {
+        let (client, server) = duplex(1024);
+        let mut connection = Connection::from_stream(client, Codec::new());
+        assert!(!connection.is_secure());
+
+        connection
+            .send(Command::NICK("moose".to_owned()).into())
+            .await
+            .unwrap();
+        let mut server = BufReader::new(server);
+        let mut line = String::new();
+        server.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "NICK moose\r\n");
+
+        server.write_all(b"PING :moose\r\n").await.unwrap();
+        let message = connection.next().await.unwrap().unwrap().unwrap();
+        assert_eq!(message.command, Command::PING("moose".to_owned()));
+
+        drop(server);
+        assert!(connection.next().await.is_none());
+        connection.shutdown().await.unwrap();
+    }
+}
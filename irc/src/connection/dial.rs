@@ -0,0 +1,184 @@
+//! Connect to every address of a server, racing them as in happy eyeballs.
+//!
+//! Reference: https://www.rfc-editor.org/rfc/rfc8305
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+/// How long to wait on an attempt before starting the next one.
+///
+/// Reference: https://www.rfc-editor.org/rfc/rfc8305#section-5
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Address family to try first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Family {
+    /// IPv6 first, as the RFC recommends.
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+/// Alternate families, starting with the preferred one; only the bind address' family if given.
+pub fn order(addrs: Vec<SocketAddr>, family: Family, bind: Option<IpAddr>) -> Vec<SocketAddr> {
+    let mut unique: Vec<SocketAddr> = vec![];
+    for addr in addrs {
+        if !unique.contains(&addr) && bind.is_none_or(|b| b.is_ipv6() == addr.is_ipv6()) {
+            unique.push(addr);
+        }
+    }
+    let (v6, v4): (Vec<_>, Vec<_>) = unique.into_iter().partition(SocketAddr::is_ipv6);
+    let (first, second) = match family {
+        Family::Ipv4 => (v4, v6),
+        Family::Any | Family::Ipv6 => (v6, v4),
+    };
+
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+async fn attempt(addr: SocketAddr, bind: Option<IpAddr>) -> io::Result<TcpStream> {
+    let socket = if addr.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+    if let Some(bind) = bind {
+        socket.bind(SocketAddr::new(bind, 0))?;
+    }
+    socket.connect(addr).await
+}
+
+/// Connect to the first address of server that answers.
+///
+/// The system resolver returns A and AAAA records together, so we start once both are in.
+pub async fn connect(
+    server: &str,
+    port: u16,
+    family: Family,
+    bind: Option<IpAddr>,
+) -> io::Result<TcpStream> {
+    let addrs = order(lookup_host((server, port)).await?.collect(), family, bind);
+    race(addrs, bind, ATTEMPT_DELAY).await.map_err(|e| {
+        e.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no usable addresses for {server}"),
+            )
+        })
+    })
+}
+
+/// Start an attempt every delay, or as soon as one fails, until one connects.
+async fn race(
+    addrs: Vec<SocketAddr>,
+    bind: Option<IpAddr>,
+    delay: Duration,
+) -> Result<TcpStream, Option<io::Error>> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut error = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            attempts.push(attempt(addr, bind));
+        }
+        if attempts.is_empty() {
+            return Err(error);
+        }
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => error = Some(e),
+            },
+            _ = tokio::time::sleep(delay), if pending.len() > 0 => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
+
+    use tokio::net::TcpListener;
+
+    use super::{order, race, Family};
+
+    #[test]
+    fn test_order() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:1",
+            "127.0.0.1:1",
+            "[::2]:1",
+            "[::1]:1",
+            "127.0.0.2:1",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let ordered = |family, bind| {
+            order(addrs.clone(), family, bind)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ordered(Family::Any, None),
+            ["[::1]:1", "127.0.0.1:1", "[::2]:1", "127.0.0.2:1"]
+        );
+        assert_eq!(
+            ordered(Family::Ipv4, None),
+            ["127.0.0.1:1", "[::1]:1", "127.0.0.2:1", "[::2]:1"]
+        );
+        assert_eq!(
+            ordered(Family::Ipv6, Some(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+            ["127.0.0.1:1", "127.0.0.2:1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_race() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+        // nothing listens here once the listener is gone.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let bind = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let tcp = race(vec![closed, open], bind, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(tcp.peer_addr().unwrap(), open);
+        assert_eq!(tcp.local_addr().unwrap().ip(), Ipv4Addr::LOCALHOST);
+
+        assert!(race(vec![closed], None, Duration::from_secs(60))
+            .await
+            .is_err_and(|e| e.is_some()));
+        assert!(race(vec![], None, Duration::ZERO)
+            .await
+            .is_err_and(|e| e.is_none()));
+    }
+}
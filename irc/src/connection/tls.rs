@@ -1,12 +1,19 @@
 use std::{io::Cursor, path::PathBuf, sync::Arc};
 
-use tokio::{fs, net::TcpStream};
+use proto::{command::Numeric, MessageRef};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 use tokio_rustls::{
     client::TlsStream,
     rustls::{self, pki_types},
     TlsConnector,
 };
-use tokio_util::bytes::Bytes;
+use tokio_util::bytes::{Buf, Bytes, BytesMut};
+
+use crate::codec::{MAX_MESSAGE, MAX_TAGS};
 
 pub async fn connect<'a>(
     tcp: TcpStream,
@@ -65,6 +72,45 @@ pub async fn connect<'a>(
         .await?)
 }
 
+/// Ask a plaintext server to upgrade with the legacy STARTTLS command, before registering.
+///
+/// Reference: https://ircv3.net/specs/deprecated/tls
+pub async fn starttls(tcp: &mut TcpStream) -> Result<(), Error> {
+    tcp.write_all(b"STARTTLS\r\n").await?;
+
+    let mut buf = BytesMut::with_capacity(MAX_MESSAGE);
+    loop {
+        while let Some(eol) = buf.iter().position(|chr| *chr == b'\n') {
+            let line = buf.split_to(eol + 1);
+            // servers may send notices first, and anything else is not for us.
+            let Ok(message) = MessageRef::from_bytes(&line) else {
+                continue;
+            };
+            let reason = || message.command.params.last().unwrap_or(&"").to_string();
+            match message.command.name.parse::<u16>().map(Numeric::new) {
+                Ok(Numeric::RPL_STARTTLS) if buf.has_remaining() => {
+                    // anything sent before the handshake could have been injected.
+                    return Err(Error::StartTls(
+                        "data received before the tls handshake".to_owned(),
+                    ));
+                }
+                Ok(Numeric::RPL_STARTTLS) => return Ok(()),
+                Ok(Numeric::ERR_STARTTLS) => return Err(Error::StartTls(reason())),
+                Ok(Numeric::ERR_UNKNOWNCOMMAND) if message.command.params.contains(&"STARTTLS") => {
+                    return Err(Error::StartTls(reason()));
+                }
+                _ => (),
+            }
+        }
+        if buf.len() >= MAX_MESSAGE + MAX_TAGS {
+            return Err(Error::StartTls("line too long".to_owned()));
+        }
+        if tcp.read_buf(&mut buf).await? == 0 {
+            return Err(Error::StartTls("connection closed".to_owned()));
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("rustls error: {0}")]
@@ -77,4 +123,55 @@ pub enum Error {
     BadPrivateKey,
     #[error("accept invalid not allowed, please download the server's cert.")]
     NoAcceptInvalid,
+    #[error("server refused STARTTLS: {0}")]
+    StartTls(String),
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{starttls, Error};
+
+    /// Answer STARTTLS with reply, returning what the client does with it.
+    async fn reply(reply: &'static [u8]) -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 10];
+            tcp.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"STARTTLS\r\n");
+            tcp.write_all(reply).await.unwrap();
+            tcp
+        });
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        let result = starttls(&mut tcp).await;
+        drop(server.await.unwrap());
+        result
+    }
+
+    #[tokio::test]
+    async fn test_starttls() {
+        assert!(reply(
+            b":irc.host NOTICE * :*** Looking up your hostname...\r\n:irc.host 670 * :STARTTLS successful, go ahead with TLS handshake\r\n"
+        )
+        .await
+        .is_ok());
+        assert!(matches!(
+            reply(b":irc.host 691 * :STARTTLS failure\r\n").await,
+            Err(Error::StartTls(reason)) if reason == "STARTTLS failure"
+        ));
+        assert!(matches!(
+            reply(b":irc.host 421 * STARTTLS :Unknown command\r\n").await,
+            Err(Error::StartTls(_))
+        ));
+        assert!(matches!(
+            reply(b":irc.host 670 * :go ahead\r\n:evil PRIVMSG * :injected\r\n").await,
+            Err(Error::StartTls(_))
+        ));
+    }
 }
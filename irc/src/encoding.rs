@@ -0,0 +1,176 @@
+//! Character encodings for servers and channels that don't use UTF-8.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+/// Characters 0x80 to 0x9F of windows-1252; unassigned bytes map to the C1 control they replace.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8D}', '\u{017D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{9D}', '\u{017E}', '\u{0178}',
+];
+
+/// Byte for characters we can't encode.
+const REPLACEMENT: u8 = b'?';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Charset {
+    Utf8,
+    /// ISO-8859-1
+    Latin1,
+    /// CP1252, a superset of the printable latin-1 characters.
+    Windows1252,
+}
+
+impl Charset {
+    /// Decode a line; invalid UTF-8 is replaced with U+FFFD.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        if bytes.is_ascii() {
+            return String::from_utf8_lossy(bytes);
+        }
+        match self {
+            Charset::Utf8 => String::from_utf8_lossy(bytes),
+            Charset::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Charset::Windows1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252[usize::from(b - 0x80)],
+                    _ => char::from(b),
+                })
+                .collect(),
+        }
+    }
+
+    /// Encode a line; characters the charset lacks become `?`.
+    pub fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        if text.is_ascii() {
+            return Cow::Borrowed(text.as_bytes());
+        }
+        match self {
+            Charset::Utf8 => Cow::Borrowed(text.as_bytes()),
+            Charset::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(REPLACEMENT))
+                .collect(),
+            Charset::Windows1252 => text
+                .chars()
+                .map(|c| match WINDOWS_1252.iter().position(|&w| w == c) {
+                    Some(i) => 0x80 + i as u8,
+                    None => match u8::try_from(c) {
+                        Ok(b) if !(0x80..=0x9F).contains(&b) => b,
+                        _ => REPLACEMENT,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Charset {
+    type Err = UnknownCharset;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Charset::Latin1),
+            "windows-1252" | "cp1252" => Ok(Charset::Windows1252),
+            _ => Err(UnknownCharset(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Latin1 => "latin-1",
+            Charset::Windows1252 => "windows-1252",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown charset: {0}")]
+pub struct UnknownCharset(pub String);
+
+/// How the codec decodes lines from, and encodes lines for, the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8; invalid sequences are replaced with U+FFFD.
+    #[default]
+    Lossy,
+    /// UTF-8; lines that are not valid UTF-8 are parse errors.
+    Strict,
+    /// UTF-8; lines that are not valid UTF-8 are decoded with the charset.
+    Fallback(Charset),
+    /// Every line is in this charset.
+    Legacy(Charset),
+}
+
+impl Encoding {
+    /// Decode a line, or `None` if it isn't valid in a strict encoding.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, str>> {
+        match self {
+            Encoding::Lossy => Some(String::from_utf8_lossy(bytes)),
+            Encoding::Strict => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            Encoding::Fallback(charset) => Some(match std::str::from_utf8(bytes) {
+                Ok(line) => Cow::Borrowed(line),
+                Err(_) => charset.decode(bytes),
+            }),
+            Encoding::Legacy(charset) => Some(charset.decode(bytes)),
+        }
+    }
+
+    /// Charset we send in.
+    pub fn charset(&self) -> Charset {
+        match self {
+            Encoding::Legacy(charset) => *charset,
+            _ => Charset::Utf8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Charset, Encoding};
+
+    #[test]
+    fn charsets() {
+        let tests: [(Charset, &str, &[u8]); 4] = [
+            (Charset::Utf8, "café €", "café €".as_bytes()),
+            (Charset::Latin1, "café ÿ", b"caf\xe9 \xff"),
+            (Charset::Windows1252, "café €—", b"caf\xe9 \x80\x97"),
+            (Charset::Windows1252, "\u{81}", b"\x81"),
+        ];
+        for (charset, text, bytes) in tests {
+            assert_eq!(charset.decode(bytes), text, "decoding {charset}");
+            assert_eq!(&*charset.encode(text), bytes, "encoding {charset}");
+        }
+        assert_eq!(&*Charset::Latin1.encode("€"), b"?");
+        assert_eq!("CP1252".parse::<Charset>().unwrap(), Charset::Windows1252);
+        assert!("ebcdic".parse::<Charset>().is_err());
+    }
+
+    #[test]
+    fn policies() {
+        let latin1 = b"caf\xe9";
+        assert_eq!(Encoding::Lossy.decode(latin1).unwrap(), "caf\u{FFFD}");
+        assert_eq!(Encoding::Strict.decode(latin1), None);
+        assert_eq!(
+            Encoding::Fallback(Charset::Latin1).decode(latin1).unwrap(),
+            "café"
+        );
+        assert_eq!(
+            Encoding::Fallback(Charset::Latin1)
+                .decode("café".as_bytes())
+                .unwrap(),
+            "café"
+        );
+        assert_eq!(
+            Encoding::Legacy(Charset::Latin1)
+                .decode("é".as_bytes())
+                .unwrap(),
+            "Ã©"
+        );
+    }
+}
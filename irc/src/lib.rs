@@ -2,7 +2,9 @@ pub use tokio_util::codec::BytesCodec;
 
 pub use self::codec::Codec;
 pub use self::connection::Connection;
+pub use self::encoding::{Charset, Encoding};
 
 pub mod codec;
 pub mod connection;
+pub mod encoding;
 pub use proto;